@@ -0,0 +1,57 @@
+// Runs a complete yes/no election in-process: a trusted dealer shares the
+// election key among trustees, voters cast encrypted ballots with validity
+// proofs, the ballots are tallied homomorphically and a threshold of
+// trustees decrypts the result with verifiable decryption shares.
+use num_bigint::BigUint;
+use rust_zero_knowledge::{Ballot, DecryptionShare, ZKP};
+
+const TRUSTEES: usize = 5;
+const THRESHOLD: usize = 3;
+const ELECTION_ID: &[u8] = b"example election";
+
+fn main() {
+    let (alpha, beta, p, q) = ZKP::get_constants();
+    let zkp = ZKP { alpha, beta, p, q };
+
+    // Set up the election key and give every trustee a share of it.
+    let (x, y) = zkp.elgamal_keygen();
+    let shares = zkp
+        .split_secret(&x, THRESHOLD, TRUSTEES)
+        .expect("Invalid threshold parameters");
+    let verification_keys: Vec<BigUint> = shares.iter().map(|share| zkp.compute_pair(&share.value).0).collect();
+    println!("✅ Election key shared among {} trustees (threshold {})", TRUSTEES, THRESHOLD);
+
+    // Voters cast ballots; the bulletin board only accepts valid ones.
+    let votes = [true, false, true, true, false, true, false, true, true, false];
+    let mut board: Vec<Ballot> = Vec::new();
+    for (voter, &vote) in votes.iter().enumerate() {
+        let voter_id = format!("voter {}", voter);
+        let ballot = zkp.cast_ballot(&y, ELECTION_ID, voter_id.as_bytes(), vote);
+        if zkp.verify_ballot(&y, ELECTION_ID, voter_id.as_bytes(), &ballot) {
+            board.push(ballot);
+        } else {
+            println!("Rejected ballot from voter {}", voter);
+        }
+    }
+    println!("✅ {} valid ballots on the board", board.len());
+
+    // Anyone can compute the encrypted tally from the board.
+    let tally = zkp.tally_ballots(&board);
+
+    // The first THRESHOLD trustees publish decryption shares, checked against their keys.
+    let decryption: Vec<DecryptionShare> = shares
+        .iter()
+        .take(THRESHOLD)
+        .map(|share| zkp.decryption_share(share, &tally))
+        .collect();
+    for share in &decryption {
+        let key = &verification_keys[share.index as usize - 1];
+        assert!(zkp.verify_decryption_share(key, &tally, share), "Trustee {} sent a bad share", share.index);
+    }
+    println!("✅ {} decryption shares verified", decryption.len());
+
+    let yes = zkp
+        .combine_decryption_shares(&tally, &decryption, board.len() as u64)
+        .expect("Could not decrypt the tally");
+    println!("Result: {} yes, {} no", yes, board.len() as u64 - yes);
+}
@@ -1,5 +1,8 @@
 use num_bigint::BigUint;
+use prost::Message;
 use std::io::stdin;
+use tonic::metadata::MetadataValue;
+use tonic::{Code, Request};
 
 pub mod zkp_auth {
     include!("./zkp_auth.rs");
@@ -7,9 +10,22 @@ pub mod zkp_auth {
 
 use zkp_auth::{
     auth_client::AuthClient, AuthenticationAnswerRequest, AuthenticationChallengeRequest,
-    RegisterRequest,
+    BlindCredentialCommitmentRequest, GetAccessTokenKeyRequest, IssueBlindCredentialRequest,
+    IssueTokenRequest, LogoutRequest, RedeemTokenRequest, RegisterRequest,
 };
-use rust_zero_knowledge::ZKP;
+use rust_zero_knowledge::interceptor::SESSION_MAC_METADATA_KEY;
+use rust_zero_knowledge::{request_mac_data, session_mac, DleqProof, LoginTranscript, ZKP};
+
+// Wraps `message` in a request carrying its MAC under the session key, which
+// the server checks on every request made with the session.
+fn signed<T: Message>(session_key: &[u8; 32], method: &str, message: T) -> Request<T> {
+    let tag = session_mac(session_key, &request_mac_data(method, &message.encode_to_vec()));
+    let mut request = Request::new(message);
+    request
+        .metadata_mut()
+        .insert_bin(SESSION_MAC_METADATA_KEY, MetadataValue::from_bytes(&tag));
+    request
+}
 
 #[tokio::main]
 async fn main() {
@@ -41,20 +57,23 @@ async fn main() {
     let password = BigUint::from_bytes_be(buf.trim().as_bytes());
     buf.clear();
 
-    let (y1, y2) = zkp.compute_pair(&password);
+    // Derive the secret from the password and a fresh salt kept by the server.
+    let salt = ZKP::generate_random_string(16).into_bytes();
+    let x = zkp.hash_to_challenge(b"password", &[&BigUint::from_bytes_be(&salt), &password]);
+    let (y1, y2) = zkp.compute_pair(&x);
 
     let request = RegisterRequest {
         user: username.clone(),
         y1: y1.to_bytes_be(),
         y2: y2.to_bytes_be(),
+        salt,
     };
 
-    let _response = client
-        .register(request)
-        .await
-        .expect("Could not register in server");
-
-    println!("✅ Registration was successful");
+    match client.register(request).await {
+        Ok(_) => println!("✅ Registration was successful"),
+        Err(status) if status.code() == Code::AlreadyExists => println!("User is already registered, logging in"),
+        Err(status) => panic!("Could not register in server: {}", status),
+    }
 
     println!("Please provide the password (to login):");
     stdin()
@@ -67,7 +86,7 @@ async fn main() {
     let (r1, r2) = zkp.compute_pair(&k);
 
     let request = AuthenticationChallengeRequest {
-        user: username,
+        user: username.clone(),
         r1: r1.to_bytes_be(),
         r2: r2.to_bytes_be(),
     };
@@ -80,8 +99,24 @@ async fn main() {
 
     let auth_id = response.auth_id;
     let c = BigUint::from_bytes_be(&response.c);
-
-    let s = zkp.response(&k, &c, &password);
+    let server_share = BigUint::from_bytes_be(&response.server_share);
+
+    let salt = BigUint::from_bytes_be(&response.salt);
+    let x = zkp.hash_to_challenge(b"password", &[&salt, &password]);
+
+    let s = zkp.response(&k, &c, &x);
+
+    // Derive the session key from server_share^k, bound to the login transcript.
+    let transcript = LoginTranscript {
+        user: &username,
+        auth_id: &auth_id,
+        r1: &r1,
+        r2: &r2,
+        server_share: &server_share,
+        c: &c,
+        s: &s,
+    };
+    let session_key = zkp.derive_session_key(&server_share.modpow(&k, &p), &transcript);
 
     let request = AuthenticationAnswerRequest {
         auth_id,
@@ -95,4 +130,113 @@ async fn main() {
         .into_inner();
 
     println!("Logging successful! session_id: {}", response.session_id);
-}
\ No newline at end of file
+
+    // Every token is issued under one key. Pin it with `ZKP_ACCESS_TOKEN_KEY` (hex);
+    // otherwise it is fetched once and used for this run. Evaluations under any
+    // other key fail the proof check below.
+    let token_key = match std::env::var("ZKP_ACCESS_TOKEN_KEY") {
+        Ok(hex) => BigUint::parse_bytes(hex.trim().as_bytes(), 16)
+            .expect("ZKP_ACCESS_TOKEN_KEY is not a hex number"),
+        Err(_) => {
+            let response = client
+                .get_access_token_key(GetAccessTokenKeyRequest {})
+                .await
+                .expect("Could not get the access token key from server")
+                .into_inner();
+            let key = BigUint::from_bytes_be(&response.public_key);
+            println!("Pin the access token key with ZKP_ACCESS_TOKEN_KEY={:x}", key);
+            key
+        }
+    };
+
+    // Obtain an anonymous access token: the server only sees the blinded input.
+    let token_input = ZKP::generate_random_string(32).into_bytes();
+    let (blind, blinded) = zkp.oprf_blind(&token_input);
+
+    let session_id = response.session_id;
+
+    let request = IssueTokenRequest {
+        session_id: session_id.clone(),
+        blinded_element: blinded.to_bytes_be(),
+    };
+
+    let response = client
+        .issue_token(signed(&session_key, "IssueToken", request))
+        .await
+        .expect("Could not get a token from server")
+        .into_inner();
+
+    let proof = DleqProof {
+        r1: BigUint::from_bytes_be(&response.proof_r1),
+        r2: BigUint::from_bytes_be(&response.proof_r2),
+        s: BigUint::from_bytes_be(&response.proof_s),
+    };
+    let unblinded = zkp
+        .oprf_unblind(
+            &token_key,
+            &blinded,
+            &BigUint::from_bytes_be(&response.evaluated_element),
+            &proof,
+            &blind,
+        )
+        .expect("Server returned an invalid token evaluation");
+    let token_output = zkp.oprf_finalize(&token_input, &unblinded);
+
+    let request = RedeemTokenRequest {
+        token_input,
+        token_output: token_output.to_vec(),
+    };
+
+    client
+        .redeem_token(request)
+        .await
+        .expect("Could not redeem token in server");
+
+    println!("✅ Anonymous access token redeemed");
+
+    // Obtain a blind credential: a server signature on a serial it never sees.
+    let serial = ZKP::generate_random_string(32);
+
+    let request = BlindCredentialCommitmentRequest {
+        session_id: session_id.clone(),
+    };
+
+    let response = client
+        .create_blind_credential_commitment(signed(&session_key, "CreateBlindCredentialCommitment", request))
+        .await
+        .expect("Could not open a blind credential in server")
+        .into_inner();
+
+    let credential_key = BigUint::from_bytes_be(&response.public_key);
+    let state = zkp.blind_challenge(
+        &credential_key,
+        &BigUint::from_bytes_be(&response.r),
+        serial.as_bytes(),
+    );
+
+    let request = IssueBlindCredentialRequest {
+        session_id: session_id.clone(),
+        credential_id: response.credential_id,
+        e: state.e.to_bytes_be(),
+    };
+
+    let response = client
+        .issue_blind_credential(signed(&session_key, "IssueBlindCredential", request))
+        .await
+        .expect("Could not get a blind credential from server")
+        .into_inner();
+
+    let credential = zkp
+        .unblind_signature(state, &credential_key, &BigUint::from_bytes_be(&response.s))
+        .expect("Server returned an invalid blind credential");
+    assert!(zkp.verify_signature(serial.as_bytes(), &credential_key, &credential));
+
+    println!("✅ Blind credential issued for serial: {}", serial);
+
+    client
+        .logout(signed(&session_key, "Logout", LogoutRequest { session_id }))
+        .await
+        .expect("Could not log out");
+
+    println!("✅ Logged out");
+}
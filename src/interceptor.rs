@@ -0,0 +1,138 @@
+//! A tonic interceptor that requires a login on other gRPC services.
+//!
+//! Clients send the signed login token from `VerifyAuthentication` in the
+//! `x-session-token-bin` metadata entry. The interceptor rejects requests
+//! without a valid token with `Unauthenticated` and otherwise stores an
+//! [`AuthenticatedUser`] in the request extensions.
+//!
+//! Session ids are only accepted by the Auth service, which checks a MAC
+//! under the session key on every request. An interceptor only sees the
+//! metadata, so it could not tell a leaked session id from its owner.
+//!
+//! Wrap a service with `tonic::service::interceptor::InterceptedService::new(service, interceptor)`,
+//! or use `tonic::service::interceptor(interceptor)` as a layer for a whole server.
+
+use crate::token::TokenVerifier;
+use crate::ZKP;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use tonic::metadata::MetadataMap;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+/// Binary metadata entry carrying a signed login token.
+pub const SESSION_TOKEN_METADATA_KEY: &str = "x-session-token-bin";
+/// Binary metadata entry carrying a request MAC under the session key, see
+/// [`crate::request_mac_data`]. The Auth service requires it on session requests.
+pub const SESSION_MAC_METADATA_KEY: &str = "x-session-mac-bin";
+
+/// The user a request was authenticated as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthenticatedUser {
+    pub user_name: String,
+    /// When the token stops being valid.
+    pub expires_at: SystemTime,
+}
+
+/// Returns the user the interceptor authenticated `request` as.
+pub fn authenticated_user<T>(request: &Request<T>) -> Option<&AuthenticatedUser> {
+    request.extensions().get::<AuthenticatedUser>()
+}
+
+/// Accepts requests that carry a valid signed token.
+#[derive(Debug, Clone, Default)]
+pub struct SessionInterceptor {
+    tokens: Option<Arc<RwLock<TokenVerifier>>>,
+}
+
+impl SessionInterceptor {
+    /// Creates an interceptor that accepts nothing until a token verifier is added.
+    pub fn new() -> Self {
+        SessionInterceptor::default()
+    }
+
+    /// Accepts tokens that `tokens` verifies. The verifier is shared so its
+    /// keys can be updated as the Auth server rotates them.
+    pub fn with_tokens(mut self, tokens: Arc<RwLock<TokenVerifier>>) -> Self {
+        self.tokens = Some(tokens);
+        self
+    }
+
+    /// Checks the login carried in `metadata`.
+    ///
+    /// # Returns
+    /// The authenticated user, or `None` if there is no login the interceptor accepts.
+    pub fn authenticate(&self, metadata: &MetadataMap) -> Option<AuthenticatedUser> {
+        if let (Some(tokens), Some(token)) = (&self.tokens, metadata.get_bin(SESSION_TOKEN_METADATA_KEY)) {
+            let token = token.to_bytes().ok()?;
+            let (alpha, beta, p, q) = ZKP::get_constants();
+            let zkp = ZKP { alpha, beta, p, q };
+            let claims = tokens.read().unwrap().verify(&zkp, &token, SystemTime::now())?;
+            return Some(AuthenticatedUser {
+                user_name: claims.user,
+                expires_at: SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(claims.expires_at),
+            });
+        }
+
+        None
+    }
+}
+
+impl Interceptor for SessionInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let user = self
+            .authenticate(request.metadata())
+            .ok_or_else(|| Status::unauthenticated("Session is not valid"))?;
+        request.extensions_mut().insert(user);
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::token::{unix_time, TokenClaims, TokenIssuer};
+    use tonic::metadata::MetadataValue;
+
+    /// Requests pass with a valid token, carrying the user in their extensions,
+    /// and are refused otherwise.
+    #[test]
+    fn test_session_interceptor() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+
+        // Share a token verifier with the interceptor.
+        let issuer = TokenIssuer::new(&zkp);
+        let tokens = Arc::new(RwLock::new(TokenVerifier::new(issuer.public_keys())));
+        let mut interceptor = SessionInterceptor::new().with_tokens(Arc::clone(&tokens));
+
+        // A signed token is accepted.
+        let now = SystemTime::now();
+        let claims = TokenClaims {
+            user: "bob".to_string(),
+            issued_at: unix_time(now),
+            expires_at: unix_time(now) + 60,
+            auth_method: "chaum-pedersen".to_string(),
+            group_id: "rfc5114-1024-160".to_string(),
+        };
+        let token = issuer.issue(&zkp, claims).to_bytes();
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert_bin(SESSION_TOKEN_METADATA_KEY, MetadataValue::from_bytes(&token));
+        let request = interceptor.call(request).unwrap();
+        assert_eq!(authenticated_user(&request).unwrap().user_name, "bob");
+
+        // Once the verifier no longer knows its key the same token is refused.
+        *tokens.write().unwrap() = TokenVerifier::default();
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert_bin(SESSION_TOKEN_METADATA_KEY, MetadataValue::from_bytes(&token));
+        assert!(interceptor.call(request).is_err());
+
+        // A request without credentials is refused.
+        let status = interceptor.call(Request::new(())).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    }
+}
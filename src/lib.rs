@@ -1,6 +1,13 @@
+use hmac::{Hmac, Mac};
 use num_bigint::{BigUint, RandBigInt};
 use rand::rngs::OsRng;
+use rand::seq::SliceRandom;
 use rand::Rng;
+use sha2::{Digest, Sha256};
+
+pub mod interceptor;
+pub mod session;
+pub mod token;
 
 pub struct ZKP {
     pub p: BigUint,
@@ -31,7 +38,6 @@ impl ZKP {
     /// Computes the response for a zero-knowledge proof challenge.
     ///
     /// # Arguments
-
     /// * `c` - The challenge value as a BigUint reference.
     /// * `x` - The secret value as a BigUint reference.
     /// * `q` - The order of the subgroup as a BigUint reference.
@@ -88,6 +94,24 @@ impl ZKP {
             .map(char::from)
             .collect()
     }
+    /// Hashes a domain separator and a list of integers into a challenge below `q`.
+    ///
+    /// This is the Fiat–Shamir transform used by the non-interactive proofs in this
+    /// crate. Each element is length-prefixed so distinct lists never collide.
+    pub fn hash_to_challenge(&self, domain: &[u8], elements: &[&BigUint]) -> BigUint {
+        let mut hasher = Sha256::new();
+        hasher.update(domain);
+        for element in elements {
+            let bytes = element.to_bytes_be();
+            hasher.update((bytes.len() as u32).to_be_bytes());
+            hasher.update(&bytes);
+        }
+        BigUint::from_bytes_be(&hasher.finalize()) % &self.q
+    }
+    /// Computes the inverse of `n` modulo the prime `p`.
+    fn inverse(&self, n: &BigUint) -> BigUint {
+        n.modpow(&(&self.p - 2u32), &self.p)
+    }
     pub fn get_constants() -> (BigUint, BigUint, BigUint, BigUint) {
         let p_str = "B10B8F96A080E01DDE92DE5EAE5D54EC52C99FBCFB06A3C69A6A9DCA52D23B616073E28675A23D189838EF1E2EE652C013ECB4AEA906112324975C3CD49B83BFACCBDD7D90C4BD7098488E9C219A73724EFFD6FAE5644738FAA31A4FF55BCCC0A151AF5F0DC8B4BD45BF37DF365C1A65E68CFDA76D4DA708DF1FB2BC2E4A4371";
         let q_str = "F518AA8781A8DF278ABA4E7D64B7CB9D49462353";
@@ -104,75 +128,2609 @@ impl ZKP {
         (alpha, beta, p, q)
     }
 }
-#[cfg(test)]
-#[cfg(test)]
-mod test {
-    use super::*;
 
-    /// Tests ZKP functionality with small, predefined values.
-    #[test]
-    fn test_toy_example_with_random_numbers() {
-        // Initialize the ZKP struct with small, predefined values.
-        let zkp = ZKP {
-            p: BigUint::from(23u32),
-            q: BigUint::from(11u32),
-            alpha: BigUint::from(4u32),
-            beta: BigUint::from(9u32),
+/// A Pedersen commitment `C = alpha^m * h^r mod p` to a value `m` under blinding `r`.
+///
+/// Binding relies on nobody knowing `log_alpha(h)`. The base `h` is therefore not
+/// `beta`, whose exponent is published in `ZKP::get_constants`, but is derived
+/// from `alpha` with `ZKP::hash_to_group` (see `ZKP::pedersen_base`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PedersenCommitment {
+    pub c: BigUint,
+}
+
+impl ZKP {
+    /// Returns the blinding base `h` of Pedersen commitments.
+    ///
+    /// `h` is hashed from `alpha` into the order-`q` subgroup, so nobody knows
+    /// `log_alpha(h)` and commitments are binding.
+    pub fn pedersen_base(&self) -> BigUint {
+        self.hash_to_group(b"zkp-pedersen-base", &self.alpha.to_bytes_be())
+    }
+
+    /// Commits to `m` with blinding factor `r`.
+    ///
+    /// # Arguments
+    /// * `m` - The committed value as a BigUint reference.
+    /// * `r` - The blinding factor as a BigUint reference, uniformly random below `q`.
+    ///
+    /// # Returns
+    /// The commitment `alpha^m * h^r mod p`, with `h` from `pedersen_base`.
+    pub fn commit(&self, m: &BigUint, r: &BigUint) -> PedersenCommitment {
+        self.commit_with_base(&self.pedersen_base(), m, r)
+    }
+
+    /// Commits to `m` with blinding factor `r` under the blinding base `h`.
+    fn commit_with_base(&self, h: &BigUint, m: &BigUint, r: &BigUint) -> PedersenCommitment {
+        let (am, _) = self.compute_pair(m);
+        PedersenCommitment { c: am * h.modpow(r, &self.p) % &self.p }
+    }
+
+    /// Checks that `commitment` opens to `m` with blinding factor `r`.
+    pub fn open(&self, commitment: &PedersenCommitment, m: &BigUint, r: &BigUint) -> bool {
+        self.commit(m, r) == *commitment
+    }
+
+    /// Homomorphically adds two commitments.
+    ///
+    /// The result commits to `m1 + m2` under blinding `r1 + r2` (both mod `q`).
+    pub fn add_commitments(&self, a: &PedersenCommitment, b: &PedersenCommitment) -> PedersenCommitment {
+        PedersenCommitment { c: (&a.c * &b.c) % &self.p }
+    }
+
+    /// Verifies a proof of knowledge of an opening of `commitment`.
+    ///
+    /// The prover picks random nonces `k1`, `k2` and sends `t = commit(k1, k2)`.
+    /// After receiving the challenge `c` it answers with `s1 = response(k1, c, m)`
+    /// and `s2 = response(k2, c, r)`.
+    ///
+    /// # Arguments
+    /// * `commitment` - The commitment whose opening is being proven.
+    /// * `t` - The prover's first message, as a BigUint reference.
+    /// * `c` - The challenge from the verifier, as a BigUint reference.
+    /// * `s1` - The response for the committed value, as a BigUint reference.
+    /// * `s2` - The response for the blinding factor, as a BigUint reference.
+    ///
+    /// # Returns
+    /// `true` if `t == alpha^s1 * h^s2 * C^c mod p`, otherwise `false`.
+    pub fn verify_opening(
+        &self,
+        commitment: &PedersenCommitment,
+        t: &BigUint,
+        c: &BigUint,
+        s1: &BigUint,
+        s2: &BigUint,
+    ) -> bool {
+        let expected = self.commit(s1, s2).c * commitment.c.modpow(c, &self.p) % &self.p;
+        *t == expected
+    }
+}
+
+/// Proof that a bit commitment `C_i = alpha^b * h^r_i` hides `b ∈ {0, 1}`.
+///
+/// This is a disjunctive (OR) Schnorr proof of knowledge of `log_h(C_i)` or
+/// `log_h(C_i / alpha)`, made non-interactive with `ZKP::hash_to_challenge`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitProof {
+    pub commitment: BigUint,
+    pub e0: BigUint,
+    pub e1: BigUint,
+    pub s0: BigUint,
+    pub s1: BigUint,
+}
+
+/// Proof that a Pedersen commitment hides a value in `[0, 2^n)`, where `n` is the
+/// number of bit proofs.
+///
+/// Encoded by `to_bytes` as a big-endian `u32` bit count followed by, for each bit,
+/// the `commitment`, `e0`, `e1`, `s0` and `s1` fields, each as a big-endian `u32`
+/// length and the big-endian bytes of the integer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeProof {
+    pub bits: Vec<BitProof>,
+}
+
+impl RangeProof {
+    /// Serializes the proof into its byte encoding.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&(self.bits.len() as u32).to_be_bytes());
+        for bit in &self.bits {
+            for n in [&bit.commitment, &bit.e0, &bit.e1, &bit.s0, &bit.s1] {
+                write_biguint(&mut out, n);
+            }
+        }
+        out
+    }
+
+    /// Parses a proof from its byte encoding, returning `None` if it is malformed.
+    pub fn from_bytes(bytes: &[u8]) -> Option<RangeProof> {
+        let mut input = bytes;
+        let count = read_u32(&mut input)?;
+        let mut bits = Vec::new();
+        for _ in 0..count {
+            bits.push(BitProof {
+                commitment: read_biguint(&mut input)?,
+                e0: read_biguint(&mut input)?,
+                e1: read_biguint(&mut input)?,
+                s0: read_biguint(&mut input)?,
+                s1: read_biguint(&mut input)?,
+            });
+        }
+        input.is_empty().then_some(RangeProof { bits })
+    }
+}
+
+impl ZKP {
+    /// Shifts a commitment to `m` into a commitment to `m - delta` with the same blinding.
+    ///
+    /// Lower bounds are proven this way: to show `m >= 18`, prove that the shifted
+    /// commitment hides a value in `[0, 2^n)`.
+    pub fn offset_commitment(&self, commitment: &PedersenCommitment, delta: &BigUint) -> PedersenCommitment {
+        let shift = self.inverse(&self.alpha.modpow(delta, &self.p));
+        PedersenCommitment { c: (&commitment.c * shift) % &self.p }
+    }
+
+    /// Proves that `commit(v, r)` hides a value in `[0, 2^bits)`.
+    ///
+    /// # Arguments
+    /// * `v` - The committed value as a BigUint reference.
+    /// * `r` - The blinding factor of the commitment as a BigUint reference.
+    /// * `bits` - The bit length of the range, below the bit length of `q`.
+    ///
+    /// # Returns
+    /// The range proof, or `None` if `v` does not fit in `bits` bits or the range
+    /// is so wide that `2^bits >= q`.
+    pub fn prove_range(&self, v: &BigUint, r: &BigUint, bits: usize) -> Option<RangeProof> {
+        if bits == 0 || bits as u64 >= self.q.bits() || v.bits() > bits as u64 {
+            return None;
+        }
+        let h = self.pedersen_base();
+        let commitment = self.commit_with_base(&h, v, r);
+
+        // Pick blindings for bits 1.. at random and solve for bit 0 so that
+        // the weighted product of the bit commitments equals the commitment.
+        let mut blindings = vec![BigUint::from(0u32); bits];
+        let mut weighted = BigUint::from(0u32);
+        for (i, blinding) in blindings.iter_mut().enumerate().skip(1) {
+            *blinding = ZKP::generate_random_below(&self.q);
+            weighted += &*blinding << i;
+        }
+        blindings[0] = (r + &self.q - weighted % &self.q) % &self.q;
+
+        let proofs = blindings
+            .iter()
+            .enumerate()
+            .map(|(i, blinding)| self.prove_bit(&h, &commitment, i, v.bit(i as u64), blinding))
+            .collect();
+        Some(RangeProof { bits: proofs })
+    }
+
+    /// Verifies that `commitment` hides a value in `[0, 2^bits)`.
+    ///
+    /// Ranges with `2^bits >= q` are rejected: the bit sum would wrap modulo `q`,
+    /// so any commitment could be proven in range.
+    pub fn verify_range(&self, commitment: &PedersenCommitment, proof: &RangeProof, bits: usize) -> bool {
+        if bits == 0 || bits as u64 >= self.q.bits() || proof.bits.len() != bits {
+            return false;
+        }
+        let h = self.pedersen_base();
+        let mut product = BigUint::from(1u32);
+        for (i, bit) in proof.bits.iter().enumerate() {
+            if !self.verify_bit(&h, commitment, i, bit) {
+                return false;
+            }
+            let weight = BigUint::from(1u32) << i;
+            product = product * bit.commitment.modpow(&weight, &self.p) % &self.p;
+        }
+        product == commitment.c
+    }
+
+    /// Proves that the `index`-th bit commitment hides `bit`.
+    fn prove_bit(&self, h: &BigUint, commitment: &PedersenCommitment, index: usize, bit: bool, blinding: &BigUint) -> BitProof {
+        let m = BigUint::from(bit as u32);
+        let bit_commitment = self.commit_with_base(h, &m, blinding).c;
+        let targets = self.bit_targets(&bit_commitment);
+        let real = bit as usize;
+        let fake = 1 - real;
+
+        // Simulate the branch for the bit we do not hold.
+        let mut e = [BigUint::from(0u32), BigUint::from(0u32)];
+        let mut s = [BigUint::from(0u32), BigUint::from(0u32)];
+        let mut t = [BigUint::from(0u32), BigUint::from(0u32)];
+        e[fake] = ZKP::generate_random_below(&self.q);
+        s[fake] = ZKP::generate_random_below(&self.q);
+        t[fake] = h.modpow(&s[fake], &self.p) * targets[fake].modpow(&e[fake], &self.p) % &self.p;
+
+        // Run the real branch with the challenge left over from the hash.
+        let k = ZKP::generate_random_below(&self.q);
+        t[real] = h.modpow(&k, &self.p);
+        let challenge = self.bit_challenge(commitment, index, &bit_commitment, &t[0], &t[1]);
+        e[real] = (challenge + &self.q - &e[fake]) % &self.q;
+        s[real] = self.response(&k, &e[real], blinding);
+
+        let [e0, e1] = e;
+        let [s0, s1] = s;
+        BitProof { commitment: bit_commitment, e0, e1, s0, s1 }
+    }
+
+    /// Verifies a single bit proof of a range proof.
+    fn verify_bit(&self, h: &BigUint, commitment: &PedersenCommitment, index: usize, proof: &BitProof) -> bool {
+        let targets = self.bit_targets(&proof.commitment);
+        let t0 = h.modpow(&proof.s0, &self.p) * targets[0].modpow(&proof.e0, &self.p) % &self.p;
+        let t1 = h.modpow(&proof.s1, &self.p) * targets[1].modpow(&proof.e1, &self.p) % &self.p;
+        let challenge = self.bit_challenge(commitment, index, &proof.commitment, &t0, &t1);
+        (&proof.e0 + &proof.e1) % &self.q == challenge
+    }
+
+    /// Returns `C_i` and `C_i / alpha`, whose discrete log to base `h` is the
+    /// blinding factor when the bit is 0 and 1 respectively.
+    fn bit_targets(&self, bit_commitment: &BigUint) -> [BigUint; 2] {
+        let shifted = bit_commitment * self.inverse(&self.alpha) % &self.p;
+        [bit_commitment.clone(), shifted]
+    }
+
+    fn bit_challenge(
+        &self,
+        commitment: &PedersenCommitment,
+        index: usize,
+        bit_commitment: &BigUint,
+        t0: &BigUint,
+        t1: &BigUint,
+    ) -> BigUint {
+        let index = BigUint::from(index);
+        self.hash_to_challenge(b"zkp-range-bit", &[&commitment.c, &index, bit_commitment, t0, t1])
+    }
+}
+
+/// A Schnorr signature `(r, s)` with `r = alpha^k mod p` and `s = k - e * x mod q`,
+/// where `e` is the Fiat–Shamir challenge over `r`, the public key and the message.
+///
+/// Encoded by `to_bytes` as `r` followed by `s`, each as a big-endian `u32` length
+/// and the big-endian bytes of the integer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchnorrSignature {
+    pub r: BigUint,
+    pub s: BigUint,
+}
+
+impl SchnorrSignature {
+    /// Serializes the signature into its byte encoding.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_biguint(&mut out, &self.r);
+        write_biguint(&mut out, &self.s);
+        out
+    }
+
+    /// Parses a signature from its byte encoding, returning `None` if it is malformed.
+    pub fn from_bytes(bytes: &[u8]) -> Option<SchnorrSignature> {
+        let mut input = bytes;
+        let r = read_biguint(&mut input)?;
+        let s = read_biguint(&mut input)?;
+        input.is_empty().then_some(SchnorrSignature { r, s })
+    }
+}
+
+impl ZKP {
+    /// Signs `message` with the secret `x` whose public key is `y1 = alpha^x mod p`.
+    ///
+    /// This is the Fiat–Shamir transform of the login protocol restricted to `alpha`:
+    /// the commitment is `r = alpha^k` and the challenge is derived from the message
+    /// instead of being chosen by the verifier.
+    pub fn sign(&self, message: &[u8], x: &BigUint) -> SchnorrSignature {
+        let y = self.alpha.modpow(x, &self.p);
+        let k = ZKP::generate_random_below(&self.q);
+        let r = self.alpha.modpow(&k, &self.p);
+        let e = self.signature_challenge(&r, &y, message);
+        let s = self.response(&k, &e, x);
+        SchnorrSignature { r, s }
+    }
+
+    /// Verifies a signature on `message` under the public key `y`.
+    ///
+    /// # Returns
+    /// `true` if `r == alpha^s * y^e mod p`, otherwise `false`.
+    pub fn verify_signature(&self, message: &[u8], y: &BigUint, sig: &SchnorrSignature) -> bool {
+        if sig.s >= self.q || sig.r >= self.p {
+            return false;
+        }
+        let e = self.signature_challenge(&sig.r, y, message);
+        sig.r == self.alpha.modpow(&sig.s, &self.p) * y.modpow(&e, &self.p) % &self.p
+    }
+
+    fn signature_challenge(&self, r: &BigUint, y: &BigUint, message: &[u8]) -> BigUint {
+        let digest = message_digest(message);
+        self.hash_to_challenge(b"zkp-schnorr-signature", &[r, y, &digest])
+    }
+}
+
+/// The aggregated public key of a MuSig signing group.
+///
+/// Each member key `y_i` is weighted by `a_i = H(L, y_i)`, where `L` commits to the
+/// whole (sorted) key set, so the aggregate `Y = prod y_i^a_i` cannot be steered by
+/// a member who picks its key after seeing the others (rogue-key attack).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AggregateKey {
+    pub keys: Vec<BigUint>,
+    pub coefficients: Vec<BigUint>,
+    pub key: BigUint,
+}
+
+impl AggregateKey {
+    /// Returns the position of `y` in the group, if it is a member.
+    pub fn position(&self, y: &BigUint) -> Option<usize> {
+        self.keys.iter().position(|key| key == y)
+    }
+}
+
+/// A signer's nonce for one MuSig session.
+///
+/// The secret part is consumed by `ZKP::musig_partial_sign`, so a nonce cannot be
+/// used for two signatures.
+#[derive(Debug)]
+pub struct MuSigNonce {
+    k: BigUint,
+    pub r: BigUint,
+}
+
+impl ZKP {
+    /// Aggregates the public keys of a signing group.
+    ///
+    /// The keys are sorted first, so every member derives the same aggregate
+    /// regardless of the order in which the keys were collected.
+    pub fn aggregate_keys(&self, keys: &[BigUint]) -> AggregateKey {
+        let mut keys = keys.to_vec();
+        keys.sort();
+        keys.dedup();
+        let key_refs: Vec<&BigUint> = keys.iter().collect();
+        let l = self.hash_to_challenge(b"zkp-musig-keys", &key_refs);
+
+        let coefficients: Vec<BigUint> = keys
+            .iter()
+            .map(|y| self.hash_to_challenge(b"zkp-musig-coefficient", &[&l, y]))
+            .collect();
+        let key = keys
+            .iter()
+            .zip(&coefficients)
+            .fold(BigUint::from(1u32), |acc, (y, a)| acc * y.modpow(a, &self.p) % &self.p);
+        AggregateKey { keys, coefficients, key }
+    }
+
+    /// Generates a fresh nonce `r = alpha^k mod p` for a MuSig session.
+    pub fn musig_nonce(&self) -> MuSigNonce {
+        let k = ZKP::generate_random_below(&self.q);
+        let r = self.alpha.modpow(&k, &self.p);
+        MuSigNonce { k, r }
+    }
+
+    /// Computes the commitment `H(r)` that a signer publishes before revealing `r`.
+    pub fn musig_nonce_commitment(&self, r: &BigUint) -> BigUint {
+        self.hash_to_challenge(b"zkp-musig-nonce", &[r])
+    }
+
+    /// Checks the revealed nonces against their commitments and combines them.
+    ///
+    /// # Returns
+    /// The aggregate nonce `R = prod r_i mod p`, or `None` if any nonce does not
+    /// match its commitment.
+    pub fn aggregate_nonces(&self, nonces: &[BigUint], commitments: &[BigUint]) -> Option<BigUint> {
+        if nonces.len() != commitments.len() {
+            return None;
+        }
+        let mut r = BigUint::from(1u32);
+        for (nonce, commitment) in nonces.iter().zip(commitments) {
+            if self.musig_nonce_commitment(nonce) != *commitment {
+                return None;
+            }
+            r = r * nonce % &self.p;
+        }
+        Some(r)
+    }
+
+    /// Computes a signer's partial signature `s_i = k_i - e * a_i * x_i mod q`.
+    ///
+    /// # Arguments
+    /// * `group` - The aggregated key of the signing group.
+    /// * `nonce` - The signer's nonce for this session.
+    /// * `r` - The aggregate nonce returned by `aggregate_nonces`.
+    /// * `x` - The signer's secret.
+    /// * `message` - The message being signed.
+    ///
+    /// # Returns
+    /// The partial signature, or `None` if the signer's key is not in the group.
+    pub fn musig_partial_sign(
+        &self,
+        group: &AggregateKey,
+        nonce: MuSigNonce,
+        r: &BigUint,
+        x: &BigUint,
+        message: &[u8],
+    ) -> Option<BigUint> {
+        let index = group.position(&self.alpha.modpow(x, &self.p))?;
+        let e = self.signature_challenge(r, &group.key, message);
+        let weighted = x * &group.coefficients[index] % &self.q;
+        Some(self.response(&nonce.k, &e, &weighted))
+    }
+
+    /// Verifies the partial signature of the group member with public key `y`.
+    ///
+    /// # Returns
+    /// `true` if `r_i == alpha^s_i * y^(e * a_i) mod p`, otherwise `false`.
+    pub fn musig_verify_partial(
+        &self,
+        group: &AggregateKey,
+        y: &BigUint,
+        r_i: &BigUint,
+        r: &BigUint,
+        message: &[u8],
+        s_i: &BigUint,
+    ) -> bool {
+        let Some(index) = group.position(y) else {
+            return false;
         };
+        let e = self.signature_challenge(r, &group.key, message);
+        let exponent = e * &group.coefficients[index] % &self.q;
+        *r_i == self.alpha.modpow(s_i, &self.p) * y.modpow(&exponent, &self.p) % &self.p
+    }
 
-        // Generate random values for private key (x), nonce (k), and challenge (c).
-        let x = ZKP::generate_random_below(&zkp.q);
-        let k = ZKP::generate_random_below(&zkp.q);
-        let c = ZKP::generate_random_below(&zkp.q);
+    /// Combines the partial signatures into a Schnorr signature under the
+    /// aggregate key, verifiable with `verify_signature`.
+    pub fn musig_aggregate(&self, r: &BigUint, partials: &[BigUint]) -> SchnorrSignature {
+        let s = partials.iter().fold(BigUint::from(0u32), |acc, s_i| (acc + s_i) % &self.q);
+        SchnorrSignature { r: r.clone(), s }
+    }
+}
 
-        // Compute public keys (y1, y2) and commitments (r1, r2).
-        let y1 = ZKP::exponentiate(&zkp.alpha, &x, &zkp.p);
-        let y2 = ZKP::exponentiate(&zkp.beta, &x, &zkp.p);
-        let r1 = ZKP::exponentiate(&zkp.alpha, &k, &zkp.p);
-        let r2 = ZKP::exponentiate(&zkp.beta, &k, &zkp.p);
+/// A Shamir share `f(index) mod q` of a secret `f(0)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecretShare {
+    pub index: u32,
+    pub value: BigUint,
+}
 
-        // Compute response and verify the proof.
-        let s = zkp.response(&k, &c, &x);
-        let result = zkp.verify(&r1, &r2, &y1, &y2, &c, &s);
+impl ZKP {
+    /// Splits `x` into `n` shares so that any `threshold` of them recover it.
+    ///
+    /// # Returns
+    /// Shares with indices `1..=n`, or `None` unless `1 <= threshold <= n`.
+    pub fn split_secret(&self, x: &BigUint, threshold: usize, n: usize) -> Option<Vec<SecretShare>> {
+        self.share_polynomial(x, threshold, n).map(|(_, shares)| shares)
+    }
 
-        // Assert the proof is valid.
-        assert!(result);
+    /// Recovers the secret from at least `threshold` distinct shares.
+    ///
+    /// # Returns
+    /// The secret, or `None` if a share has index 0 or two shares have the same
+    /// index, which would make a Lagrange denominator vanish mod `q`.
+    pub fn reconstruct_secret(&self, shares: &[SecretShare]) -> Option<BigUint> {
+        let indices: Vec<u32> = shares.iter().map(|share| share.index).collect();
+        if !distinct_indices(&indices) {
+            return None;
+        }
+        Some(shares.iter().fold(BigUint::from(0u32), |acc, share| {
+            (acc + self.lagrange_coefficient(share.index, &indices) * &share.value) % &self.q
+        }))
     }
 
-    /// Tests ZKP functionality using 1024-bit constants from RFC 5114.
-    #[test]
-    fn test_with_1024_bit_constant() {
-        // Parse hexadecimal constants for p, alpha, and q as per RFC 5114.
-        let p_str = "B10B8F96A080E01DDE92DE5EAE5D54EC52C99FBCFB06A3C69A6A9DCA52D23B616073E28675A23D189838EF1E2EE652C013ECB4AEA906112324975C3CD49B83BFACCBDD7D90C4BD7098488E9C219A73724EFFD6FAE5644738FAA31A4FF55BCCC0A151AF5F0DC8B4BD45BF37DF365C1A65E68CFDA76D4DA708DF1FB2BC2E4A4371";
-        let q_str = "F518AA8781A8DF278ABA4E7D64B7CB9D49462353";
-        let g_str = "A4D1CBD5C3FD34126765A442EFB99905F8104DD258AC507FD6406CFF14266D31266FEA1E5C41564B777E690F5504F213160217B4B01B886A5E91547F9E2749F4D7FBD7D3B9A92EE1909D0D2263F80A76A6A24C087A091F531DBF0A0169B6A28AD662A4D18E73AFA32D779D5918D08BC8858F4DCEF97C2A24855E6EEB22B3B2E5";
-        //Convert strings to BigUint.
-        let p = BigUint::parse_bytes(p_str.as_bytes(), 16).unwrap();
-        let alpha = BigUint::parse_bytes(g_str.as_bytes(), 16).unwrap();
-        let q = BigUint::parse_bytes(q_str.as_bytes(), 16).unwrap();
+    /// Computes the Lagrange coefficient at zero of `index` over the set `indices`.
+    ///
+    /// # Returns
+    /// `prod_{j != index} j / (j - index) mod q`.
+    pub fn lagrange_coefficient(&self, index: u32, indices: &[u32]) -> BigUint {
+        let i = BigUint::from(index);
+        let mut numerator = BigUint::from(1u32);
+        let mut denominator = BigUint::from(1u32);
+        for &other in indices.iter().filter(|&&other| other != index) {
+            let j = BigUint::from(other);
+            numerator = numerator * &j % &self.q;
+            denominator = denominator * ((&j + &self.q - &i) % &self.q) % &self.q;
+        }
+        numerator * self.inverse_mod_q(&denominator) % &self.q
+    }
 
-        // Compute beta as alpha raised to a random exponent modulo p.
-        let beta = alpha.modpow(&ZKP::generate_random_below(&q), &p);
+    /// Combines the commitments `(alpha^k_i, beta^k_i)` of the cooperating share
+    /// holders into the `(r1, r2)` sent to the verifier.
+    pub fn combine_commitments(&self, commitments: &[(BigUint, BigUint)]) -> (BigUint, BigUint) {
+        commitments.iter().fold(
+            (BigUint::from(1u32), BigUint::from(1u32)),
+            |(r1, r2), (r1_i, r2_i)| (r1 * r1_i % &self.p, r2 * r2_i % &self.p),
+        )
+    }
 
-        // Initialize ZKP with the large constants.
-        let zkp = ZKP { p, q, alpha, beta };
+    /// Computes a share holder's contribution to the threshold response.
+    ///
+    /// # Arguments
+    /// * `k` - The share holder's nonce, committed to via `compute_pair`.
+    /// * `c` - The challenge from the verifier.
+    /// * `share` - The share holder's share of `x`.
+    /// * `indices` - The indices of all cooperating share holders.
+    ///
+    /// # Returns
+    /// `(k - c * lambda_i * x_i) mod q`; the sum of all contributions is a valid `s`.
+    pub fn partial_response(&self, k: &BigUint, c: &BigUint, share: &SecretShare, indices: &[u32]) -> BigUint {
+        let weighted = self.lagrange_coefficient(share.index, indices) * &share.value % &self.q;
+        self.response(k, c, &weighted)
+    }
 
-        // Generate random values for private key (x), nonce (k), and challenge (c).
-        let x = ZKP::generate_random_below(&zkp.q);
-        let k = ZKP::generate_random_below(&zkp.q);
-        let c = ZKP::generate_random_below(&zkp.q);
+    /// Sums the partial responses into the response `s` checked by `verify`.
+    pub fn combine_responses(&self, partials: &[BigUint]) -> BigUint {
+        partials.iter().fold(BigUint::from(0u32), |acc, s_i| (acc + s_i) % &self.q)
+    }
 
-        // Compute public keys (y1, y2) and commitments (r1, r2).
-        let y1 = ZKP::exponentiate(&zkp.alpha, &x, &zkp.p);
-        let y2 = ZKP::exponentiate(&zkp.beta, &x, &zkp.p);
-        let r1 = ZKP::exponentiate(&zkp.alpha, &k, &zkp.p);
-        let r2 = ZKP::exponentiate(&zkp.beta, &k, &zkp.p);
+    /// Picks a random polynomial of degree `threshold - 1` with constant term `secret`
+    /// and evaluates it at `1..=n`.
+    fn share_polynomial(&self, secret: &BigUint, threshold: usize, n: usize) -> Option<(Vec<BigUint>, Vec<SecretShare>)> {
+        if threshold == 0 || threshold > n || n > u32::MAX as usize {
+            return None;
+        }
+        let mut coefficients = vec![secret % &self.q];
+        coefficients.extend((1..threshold).map(|_| ZKP::generate_random_below(&self.q)));
+        let shares = (1..=n as u32)
+            .map(|index| SecretShare {
+                index,
+                value: self.evaluate_polynomial(&coefficients, &BigUint::from(index)),
+            })
+            .collect();
+        Some((coefficients, shares))
+    }
 
-        // Compute response and verify the proof.
-        let s = zkp.response(&k, &c, &x);
-        let result = zkp.verify(&r1, &r2, &y1, &y2, &c, &s);
+    /// Evaluates the polynomial with the given coefficients at `at`, modulo `q`.
+    fn evaluate_polynomial(&self, coefficients: &[BigUint], at: &BigUint) -> BigUint {
+        coefficients
+            .iter()
+            .rev()
+            .fold(BigUint::from(0u32), |acc, coefficient| (acc * at + coefficient) % &self.q)
+    }
 
-        // Assert the proof is valid.
-        assert!(result);
+    /// Computes the inverse of `n` modulo the prime `q`.
+    fn inverse_mod_q(&self, n: &BigUint) -> BigUint {
+        n.modpow(&(&self.q - 2u32), &self.q)
+    }
+}
+
+/// Checks that share indices are non-zero and pairwise distinct.
+fn distinct_indices(indices: &[u32]) -> bool {
+    let mut seen = std::collections::HashSet::new();
+    indices.iter().all(|&index| index != 0 && seen.insert(index))
+}
+
+/// A non-interactive Chaum–Pedersen proof that `log_alpha(y1) == log_beta(y2)`.
+///
+/// `r1` and `r2` are the prover's commitments; the challenge is recomputed from the
+/// statement with `ZKP::hash_to_challenge` and checked with `ZKP::verify`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DleqProof {
+    pub r1: BigUint,
+    pub r2: BigUint,
+    pub s: BigUint,
+}
+
+/// An ElGamal ciphertext `(alpha^r, m * y^r) mod p`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ciphertext {
+    pub c1: BigUint,
+    pub c2: BigUint,
+}
+
+impl ZKP {
+    /// Returns the same group with `beta` replaced by another base.
+    ///
+    /// Equality-of-logs statements against arbitrary bases (a ciphertext, a hashed
+    /// input) reuse `compute_pair` and `verify` through this.
+    pub fn with_beta(&self, beta: &BigUint) -> ZKP {
+        ZKP {
+            p: self.p.clone(),
+            q: self.q.clone(),
+            alpha: self.alpha.clone(),
+            beta: beta.clone(),
+        }
+    }
+
+    /// Proves knowledge of `x` such that `y1 = alpha^x` and `y2 = beta^x`.
+    pub fn prove_dleq(&self, x: &BigUint) -> DleqProof {
+        self.prove_dleq_bound(x, &[])
+    }
+
+    /// Verifies a proof that `log_alpha(y1) == log_beta(y2)`.
+    pub fn verify_dleq(&self, y1: &BigUint, y2: &BigUint, proof: &DleqProof) -> bool {
+        self.verify_dleq_bound(y1, y2, proof, &[])
+    }
+
+    /// Like `prove_dleq`, but the challenge also covers `binding`, so the proof
+    /// only verifies for that context.
+    pub fn prove_dleq_bound(&self, x: &BigUint, binding: &[u8]) -> DleqProof {
+        let (y1, y2) = self.compute_pair(x);
+        let k = ZKP::generate_random_below(&self.q);
+        let (r1, r2) = self.compute_pair(&k);
+        let c = self.dleq_challenge(&y1, &y2, &r1, &r2, binding);
+        let s = self.response(&k, &c, x);
+        DleqProof { r1, r2, s }
+    }
+
+    /// Verifies a proof made by `prove_dleq_bound` for the same `binding`.
+    pub fn verify_dleq_bound(&self, y1: &BigUint, y2: &BigUint, proof: &DleqProof, binding: &[u8]) -> bool {
+        let c = self.dleq_challenge(y1, y2, &proof.r1, &proof.r2, binding);
+        self.verify(&proof.r1, &proof.r2, y1, y2, &c, &proof.s)
+    }
+
+    fn dleq_challenge(&self, y1: &BigUint, y2: &BigUint, r1: &BigUint, r2: &BigUint, binding: &[u8]) -> BigUint {
+        let binding = message_digest(binding);
+        self.hash_to_challenge(b"zkp-dleq", &[&self.alpha, &self.beta, y1, y2, r1, r2, &binding])
+    }
+
+    /// Generates an ElGamal key pair `(x, y = alpha^x mod p)`.
+    pub fn elgamal_keygen(&self) -> (BigUint, BigUint) {
+        let x = ZKP::generate_random_below(&self.q);
+        let y = self.alpha.modpow(&x, &self.p);
+        (x, y)
+    }
+
+    /// Encrypts the group element `m` under the public key `y`.
+    ///
+    /// `m` must lie in the subgroup generated by `alpha` for the ciphertext to hide it.
+    pub fn encrypt(&self, y: &BigUint, m: &BigUint) -> Ciphertext {
+        let r = ZKP::generate_random_below(&self.q);
+        Ciphertext {
+            c1: self.alpha.modpow(&r, &self.p),
+            c2: m * y.modpow(&r, &self.p) % &self.p,
+        }
+    }
+
+    /// Decrypts a ciphertext with the secret `x`, returning `c2 / c1^x mod p`.
+    pub fn decrypt(&self, x: &BigUint, ciphertext: &Ciphertext) -> BigUint {
+        let shared = ciphertext.c1.modpow(x, &self.p);
+        &ciphertext.c2 * self.inverse(&shared) % &self.p
+    }
+
+    /// Encrypts the small integer `v` as `alpha^v` (exponential ElGamal).
+    ///
+    /// Ciphertexts of this form add their plaintexts under `add_ciphertexts`.
+    pub fn encrypt_exponential(&self, y: &BigUint, v: u64) -> Ciphertext {
+        self.encrypt(y, &self.alpha.modpow(&BigUint::from(v), &self.p))
+    }
+
+    /// Decrypts an exponential ElGamal ciphertext by searching `v` in `[0, max]`.
+    ///
+    /// # Returns
+    /// The plaintext, or `None` if it is larger than `max`.
+    pub fn decrypt_exponential(&self, x: &BigUint, ciphertext: &Ciphertext, max: u64) -> Option<u64> {
+        self.discrete_log(&self.decrypt(x, ciphertext), max)
+    }
+
+    /// Multiplies two ciphertexts component-wise.
+    ///
+    /// The result encrypts the product of the plaintexts, or for exponential
+    /// ElGamal their sum.
+    pub fn add_ciphertexts(&self, a: &Ciphertext, b: &Ciphertext) -> Ciphertext {
+        Ciphertext {
+            c1: &a.c1 * &b.c1 % &self.p,
+            c2: &a.c2 * &b.c2 % &self.p,
+        }
+    }
+
+    /// Decrypts a ciphertext and proves that the decryption used the secret behind `y`.
+    ///
+    /// # Returns
+    /// The plaintext `m` and a proof that `log_alpha(y) == log_c1(c2 / m)`.
+    pub fn prove_decryption(&self, x: &BigUint, ciphertext: &Ciphertext) -> (BigUint, DleqProof) {
+        let m = self.decrypt(x, ciphertext);
+        let proof = self.with_beta(&ciphertext.c1).prove_dleq(x);
+        (m, proof)
+    }
+
+    /// Verifies that `m` is the decryption of `ciphertext` under the public key `y`.
+    ///
+    /// `y`, `c1` and the shared value `c2 / m` must be non-identity elements of the
+    /// order-`q` subgroup; with `c1 = 0` the proof would hold for any `m`.
+    pub fn verify_decryption(&self, y: &BigUint, ciphertext: &Ciphertext, m: &BigUint, proof: &DleqProof) -> bool {
+        if m.bits() == 0 || *m >= self.p || !self.in_subgroup(y) || !self.in_subgroup(&ciphertext.c1) {
+            return false;
+        }
+        let shared = &ciphertext.c2 * self.inverse(m) % &self.p;
+        self.in_subgroup(&shared) && self.with_beta(&ciphertext.c1).verify_dleq(y, &shared, proof)
+    }
+
+    /// Finds `v <= max` with `alpha^v == target mod p` by exhaustive search.
+    fn discrete_log(&self, target: &BigUint, max: u64) -> Option<u64> {
+        let mut current = BigUint::from(1u32);
+        for v in 0..=max {
+            if current == *target {
+                return Some(v);
+            }
+            current = current * &self.alpha % &self.p;
+        }
+        None
+    }
+}
+
+/// A VRF proof: the output point `gamma = H(m)^x` and a proof that
+/// `log_alpha(y) == log_H(m)(gamma)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VrfProof {
+    pub gamma: BigUint,
+    pub proof: DleqProof,
+}
+
+impl ZKP {
+    /// Hashes `message` to an element of the order-`q` subgroup of `Z_p^*`.
+    ///
+    /// The digest is expanded to 128 bits more than `p`, reduced mod `p` and raised
+    /// to the cofactor `(p - 1) / q`. Nobody learns the discrete log of the result.
+    pub fn hash_to_group(&self, domain: &[u8], message: &[u8]) -> BigUint {
+        let cofactor = (&self.p - 1u32) / &self.q;
+        let blocks = (self.p.bits() as usize + 128).div_ceil(256);
+        for counter in 0u32.. {
+            let mut expanded = Vec::with_capacity(blocks * 32);
+            for block in 0..blocks as u32 {
+                let mut hasher = Sha256::new();
+                hasher.update(domain);
+                hasher.update(counter.to_be_bytes());
+                hasher.update(block.to_be_bytes());
+                hasher.update(message);
+                expanded.extend_from_slice(&hasher.finalize());
+            }
+            let h = (BigUint::from_bytes_be(&expanded) % &self.p).modpow(&cofactor, &self.p);
+            if h > BigUint::from(1u32) {
+                return h;
+            }
+        }
+        unreachable!("hash_to_group exhausted its counter")
+    }
+
+    /// Evaluates the VRF on `message` with the secret `x`.
+    pub fn vrf_prove(&self, x: &BigUint, message: &[u8]) -> VrfProof {
+        let h = self.hash_to_group(b"zkp-vrf", message);
+        let gamma = h.modpow(x, &self.p);
+        let proof = self.with_beta(&h).prove_dleq(x);
+        VrfProof { gamma, proof }
+    }
+
+    /// Verifies a VRF proof for `message` under the public key `y = alpha^x mod p`.
+    pub fn vrf_verify(&self, y: &BigUint, message: &[u8], proof: &VrfProof) -> bool {
+        if !self.in_subgroup(&proof.gamma) {
+            return false;
+        }
+        let h = self.hash_to_group(b"zkp-vrf", message);
+        self.with_beta(&h).verify_dleq(y, &proof.gamma, &proof.proof)
+    }
+
+    /// Derives the 32-byte pseudorandom VRF output from a proof.
+    ///
+    /// Only call this on proofs accepted by `vrf_verify`.
+    pub fn vrf_proof_to_hash(&self, proof: &VrfProof) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(b"zkp-vrf-output");
+        hasher.update(proof.gamma.to_bytes_be());
+        hasher.finalize().into()
+    }
+
+    /// Checks that `n` is a non-identity element of the order-`q` subgroup.
+    pub fn in_subgroup(&self, n: &BigUint) -> bool {
+        *n > BigUint::from(1u32) && *n < self.p && n.modpow(&self.q, &self.p) == BigUint::from(1u32)
+    }
+}
+
+impl ZKP {
+    /// Blinds a token input for a verifiable OPRF evaluation.
+    ///
+    /// # Returns
+    /// The blinding scalar `r`, kept by the client, and the blinded element
+    /// `H(input)^r mod p` sent to the server.
+    pub fn oprf_blind(&self, input: &[u8]) -> (BigUint, BigUint) {
+        let h = self.hash_to_group(b"zkp-oprf", input);
+        let r = loop {
+            let r = ZKP::generate_random_below(&self.q);
+            if r.bits() > 0 {
+                break r;
+            }
+        };
+        let blinded = h.modpow(&r, &self.p);
+        (r, blinded)
+    }
+
+    /// Evaluates the OPRF with the server key `k` on a blinded element.
+    ///
+    /// # Returns
+    /// The evaluated element `blinded^k mod p` and a proof that it used the key
+    /// behind `alpha^k`, or `None` if `blinded` is not a valid group element.
+    pub fn oprf_evaluate(&self, k: &BigUint, blinded: &BigUint) -> Option<(BigUint, DleqProof)> {
+        if !self.in_subgroup(blinded) {
+            return None;
+        }
+        let evaluated = blinded.modpow(k, &self.p);
+        let proof = self.with_beta(blinded).prove_dleq(k);
+        Some((evaluated, proof))
+    }
+
+    /// Checks the server's proof and removes the blinding from an evaluated element.
+    ///
+    /// # Arguments
+    /// * `y` - The server's public key `alpha^k mod p`.
+    /// * `blinded` - The blinded element sent to the server.
+    /// * `evaluated` - The element returned by the server.
+    /// * `proof` - The server's proof of correct evaluation.
+    /// * `r` - The blinding scalar returned by `oprf_blind`.
+    ///
+    /// # Returns
+    /// `H(input)^k mod p`, or `None` if the proof does not verify.
+    pub fn oprf_unblind(
+        &self,
+        y: &BigUint,
+        blinded: &BigUint,
+        evaluated: &BigUint,
+        proof: &DleqProof,
+        r: &BigUint,
+    ) -> Option<BigUint> {
+        if !self.in_subgroup(evaluated) || !self.with_beta(blinded).verify_dleq(y, evaluated, proof) {
+            return None;
+        }
+        Some(evaluated.modpow(&self.inverse_mod_q(r), &self.p))
+    }
+
+    /// Derives the 32-byte token output from the input and the unblinded element.
+    pub fn oprf_finalize(&self, input: &[u8], unblinded: &BigUint) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(b"zkp-oprf-output");
+        hasher.update((input.len() as u32).to_be_bytes());
+        hasher.update(input);
+        hasher.update(unblinded.to_bytes_be());
+        hasher.finalize().into()
+    }
+
+    /// Evaluates the OPRF directly with the key `k`, as the server does when a
+    /// token is redeemed.
+    pub fn oprf_full_evaluate(&self, k: &BigUint, input: &[u8]) -> [u8; 32] {
+        let h = self.hash_to_group(b"zkp-oprf", input);
+        self.oprf_finalize(input, &h.modpow(k, &self.p))
+    }
+}
+
+/// A client's state while obtaining a blind Schnorr signature.
+///
+/// The signer sees only `e`; the resulting signature `(r, s + a)` cannot be linked
+/// to the signing session.
+#[derive(Debug)]
+pub struct BlindingState {
+    a: BigUint,
+    signer_r: BigUint,
+    pub r: BigUint,
+    pub e: BigUint,
+}
+
+impl ZKP {
+    /// Blinds the signer's nonce `signer_r = alpha^k` and the challenge for `message`.
+    ///
+    /// # Returns
+    /// The blinding state; `e` is sent to the signer.
+    pub fn blind_challenge(&self, y: &BigUint, signer_r: &BigUint, message: &[u8]) -> BlindingState {
+        let a = ZKP::generate_random_below(&self.q);
+        let b = ZKP::generate_random_below(&self.q);
+        let r = signer_r * self.alpha.modpow(&a, &self.p) % &self.p * y.modpow(&b, &self.p) % &self.p;
+        let challenge = self.signature_challenge(&r, y, message);
+        let e = (challenge + &self.q - b) % &self.q;
+        BlindingState { a, signer_r: signer_r.clone(), r, e }
+    }
+
+    /// Answers a blinded challenge with the nonce `k` and the secret `x`.
+    ///
+    /// Each `k` must answer exactly one challenge. Answering many sessions
+    /// concurrently with the same key exposes the signer to the ROS attack, so
+    /// signers should bound the number of open sessions.
+    pub fn blind_sign(&self, k: &BigUint, e: &BigUint, x: &BigUint) -> BigUint {
+        self.response(k, e, x)
+    }
+
+    /// Checks the signer's answer and turns it into a signature on the message.
+    ///
+    /// # Returns
+    /// A signature verifiable with `verify_signature`, or `None` if `s` is not a
+    /// valid answer from the key `y`.
+    pub fn unblind_signature(&self, state: BlindingState, y: &BigUint, s: &BigUint) -> Option<SchnorrSignature> {
+        let expected = self.alpha.modpow(s, &self.p) * y.modpow(&state.e, &self.p) % &self.p;
+        if expected != state.signer_r {
+            return None;
+        }
+        let s = (s + &state.a) % &self.q;
+        Some(SchnorrSignature { r: state.r, s })
+    }
+}
+
+/// A linkable ring signature (LSAG) by one member of a ring of public keys.
+///
+/// `key_image = H(scope)^x` is the same for every signature a member makes in a
+/// scope (an election, an approval round) whatever ring was used, so two
+/// signatures with the same image were made by the same member.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RingSignature {
+    pub c0: BigUint,
+    pub s: Vec<BigUint>,
+    pub key_image: BigUint,
+}
+
+impl RingSignature {
+    /// Returns `true` if both signatures were made by the same ring member.
+    pub fn is_linked_to(&self, other: &RingSignature) -> bool {
+        self.key_image == other.key_image
+    }
+}
+
+impl ZKP {
+    /// Signs `message` on behalf of `ring` with the secret `x` of one of its members.
+    ///
+    /// # Arguments
+    /// * `ring` - The public keys `y = alpha^x mod p` of the ring members.
+    /// * `x` - The signer's secret.
+    /// * `scope` - The context within which signatures are linkable.
+    /// * `message` - The message being signed.
+    ///
+    /// # Returns
+    /// The ring signature, or `None` if `alpha^x` is not in `ring`.
+    pub fn ring_sign(&self, ring: &[BigUint], x: &BigUint, scope: &[u8], message: &[u8]) -> Option<RingSignature> {
+        let (y, _) = self.compute_pair(x);
+        let signer = ring.iter().position(|member| *member == y)?;
+        let n = ring.len();
+        let h = self.hash_to_group(b"zkp-lsag-scope", scope);
+        let key_image = h.modpow(x, &self.p);
+        let context = self.ring_context(ring, &h, &key_image, message);
+
+        // Start the ring right after the signer with a fresh nonce ...
+        let u = ZKP::generate_random_below(&self.q);
+        let mut c = vec![BigUint::from(0u32); n];
+        let mut s = vec![BigUint::from(0u32); n];
+        c[(signer + 1) % n] = self.ring_challenge(&context, &self.alpha.modpow(&u, &self.p), &h.modpow(&u, &self.p));
+
+        // ... walk the other members with random responses ...
+        let mut i = (signer + 1) % n;
+        while i != signer {
+            s[i] = ZKP::generate_random_below(&self.q);
+            let (l, r) = self.ring_commitments(&ring[i], &h, &key_image, &c[i], &s[i]);
+            c[(i + 1) % n] = self.ring_challenge(&context, &l, &r);
+            i = (i + 1) % n;
+        }
+
+        // ... and close the ring with the signer's real response.
+        s[signer] = self.response(&u, &c[signer], x);
+        Some(RingSignature { c0: c[0].clone(), s, key_image })
+    }
+
+    /// Verifies a ring signature on `message` for `ring` within `scope`.
+    pub fn verify_ring_signature(&self, ring: &[BigUint], scope: &[u8], message: &[u8], sig: &RingSignature) -> bool {
+        if ring.is_empty() || sig.s.len() != ring.len() || !self.in_subgroup(&sig.key_image) {
+            return false;
+        }
+        let h = self.hash_to_group(b"zkp-lsag-scope", scope);
+        let context = self.ring_context(ring, &h, &sig.key_image, message);
+        let mut c = sig.c0.clone();
+        for (y, s) in ring.iter().zip(&sig.s) {
+            if *s >= self.q {
+                return false;
+            }
+            let (l, r) = self.ring_commitments(y, &h, &sig.key_image, &c, s);
+            c = self.ring_challenge(&context, &l, &r);
+        }
+        c == sig.c0
+    }
+
+    /// Computes `alpha^s * y^c` and `H^s * I^c`, the commitments of one ring member.
+    fn ring_commitments(
+        &self,
+        y: &BigUint,
+        h: &BigUint,
+        key_image: &BigUint,
+        c: &BigUint,
+        s: &BigUint,
+    ) -> (BigUint, BigUint) {
+        let l = self.alpha.modpow(s, &self.p) * y.modpow(c, &self.p) % &self.p;
+        let r = h.modpow(s, &self.p) * key_image.modpow(c, &self.p) % &self.p;
+        (l, r)
+    }
+
+    /// Binds the ring, scope, key image and message into one value for the challenges.
+    fn ring_context(&self, ring: &[BigUint], h: &BigUint, key_image: &BigUint, message: &[u8]) -> BigUint {
+        let mut elements: Vec<&BigUint> = ring.iter().collect();
+        let digest = message_digest(message);
+        elements.extend([h, key_image, &digest]);
+        self.hash_to_challenge(b"zkp-lsag-context", &elements)
+    }
+
+    fn ring_challenge(&self, context: &BigUint, l: &BigUint, r: &BigUint) -> BigUint {
+        self.hash_to_challenge(b"zkp-lsag", &[context, l, r])
+    }
+}
+
+/// The hash function of a `MerkleTree`.
+///
+/// Leaves and inner nodes must be hashed with different domain separation so a
+/// node cannot be passed off as a leaf.
+pub trait MerkleHasher {
+    fn hash_leaf(&self, data: &[u8]) -> Vec<u8>;
+    fn hash_nodes(&self, left: &[u8], right: &[u8]) -> Vec<u8>;
+}
+
+/// SHA-256 Merkle hashing with `0x00`/`0x01` prefixes for leaves and nodes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha256Hasher;
+
+impl MerkleHasher for Sha256Hasher {
+    fn hash_leaf(&self, data: &[u8]) -> Vec<u8> {
+        Sha256::new().chain_update([0u8]).chain_update(data).finalize().to_vec()
+    }
+
+    fn hash_nodes(&self, left: &[u8], right: &[u8]) -> Vec<u8> {
+        Sha256::new().chain_update([1u8]).chain_update(left).chain_update(right).finalize().to_vec()
+    }
+}
+
+/// The sibling hashes from a leaf up to the root of a `MerkleTree`.
+///
+/// A node without a sibling (the last node of an odd-sized level) is carried up
+/// unchanged and contributes no sibling, which is why the verifier needs `leaf_count`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerklePath {
+    pub index: usize,
+    pub leaf_count: usize,
+    pub siblings: Vec<Vec<u8>>,
+}
+
+/// A Merkle tree over a list of leaves, with a pluggable hash.
+#[derive(Debug, Clone, Default)]
+pub struct MerkleTree<H: MerkleHasher = Sha256Hasher> {
+    hasher: H,
+    levels: Vec<Vec<Vec<u8>>>,
+}
+
+impl<H: MerkleHasher> MerkleTree<H> {
+    /// Builds a tree over `leaves` using `hasher`.
+    pub fn new(hasher: H, leaves: &[Vec<u8>]) -> Self {
+        let level = leaves.iter().map(|leaf| hasher.hash_leaf(leaf)).collect();
+        let mut tree = MerkleTree { hasher, levels: vec![level] };
+        tree.rebuild();
+        tree
+    }
+
+    /// Returns the number of leaves in the tree.
+    pub fn len(&self) -> usize {
+        self.levels.first().map_or(0, Vec::len)
+    }
+
+    /// Returns `true` if the tree has no leaves.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Appends a leaf and returns its index.
+    pub fn push(&mut self, leaf: &[u8]) -> usize {
+        if self.levels.is_empty() {
+            self.levels.push(Vec::new());
+        }
+        self.levels[0].push(self.hasher.hash_leaf(leaf));
+        let index = self.len() - 1;
+        self.update_path(index);
+        index
+    }
+
+    /// Replaces the leaf at `index`; returns `false` if there is no such leaf.
+    pub fn set(&mut self, index: usize, leaf: &[u8]) -> bool {
+        if index >= self.len() {
+            return false;
+        }
+        self.levels[0][index] = self.hasher.hash_leaf(leaf);
+        self.update_path(index);
+        true
+    }
+
+    /// Returns the root hash, or the hash of an empty leaf for an empty tree.
+    pub fn root(&self) -> Vec<u8> {
+        match self.levels.last().and_then(|level| level.first()) {
+            Some(root) => root.clone(),
+            None => self.hasher.hash_leaf(&[]),
+        }
+    }
+
+    /// Returns the inclusion path of the leaf at `index`.
+    pub fn path(&self, index: usize) -> Option<MerklePath> {
+        if index >= self.len() {
+            return None;
+        }
+        let mut siblings = Vec::new();
+        let mut position = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = position ^ 1;
+            if sibling < level.len() {
+                siblings.push(level[sibling].clone());
+            }
+            position /= 2;
+        }
+        Some(MerklePath { index, leaf_count: self.len(), siblings })
+    }
+
+    /// Checks that `leaf` is included under `root` at the position given by `path`.
+    pub fn verify_path(hasher: &H, root: &[u8], leaf: &[u8], path: &MerklePath) -> bool {
+        if path.index >= path.leaf_count {
+            return false;
+        }
+        let mut hash = hasher.hash_leaf(leaf);
+        let mut siblings = path.siblings.iter();
+        let (mut position, mut width) = (path.index, path.leaf_count);
+        while width > 1 {
+            if position % 2 == 1 {
+                let Some(sibling) = siblings.next() else { return false };
+                hash = hasher.hash_nodes(sibling, &hash);
+            } else if position + 1 < width {
+                let Some(sibling) = siblings.next() else { return false };
+                hash = hasher.hash_nodes(&hash, sibling);
+            }
+            position /= 2;
+            width = width.div_ceil(2);
+        }
+        siblings.next().is_none() && hash == root
+    }
+
+    /// Recomputes the inner levels from the leaf hashes.
+    fn rebuild(&mut self) {
+        self.levels.truncate(1);
+        while self.levels[self.levels.len() - 1].len() > 1 {
+            let next = self.levels[self.levels.len() - 1]
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => self.hasher.hash_nodes(left, right),
+                    [single] => single.clone(),
+                    _ => unreachable!(),
+                })
+                .collect();
+            self.levels.push(next);
+        }
+    }
+
+    /// Recomputes the nodes from the leaf at `index` up to the root, in
+    /// O(log n) hashes. Only these nodes change when that leaf is replaced
+    /// or appended, since an appended leaf is the last node of every level.
+    fn update_path(&mut self, index: usize) {
+        let mut position = index;
+        let mut depth = 0;
+        while self.levels[depth].len() > 1 {
+            let level = &self.levels[depth];
+            let parent = position / 2;
+            let node = match level.get(2 * parent + 1) {
+                Some(right) => self.hasher.hash_nodes(&level[2 * parent], right),
+                None => level[2 * parent].clone(),
+            };
+            if depth + 1 == self.levels.len() {
+                self.levels.push(Vec::new());
+            }
+            let next = &mut self.levels[depth + 1];
+            if parent < next.len() {
+                next[parent] = node;
+            } else {
+                next.push(node);
+            }
+            position = parent;
+            depth += 1;
+        }
+        self.levels.truncate(depth + 1);
+    }
+}
+
+/// Proof that a registration `(y1, y2)` is a leaf of the registered-key tree and
+/// that the prover knows its secret, bound to the tree root and a verifier context.
+///
+/// The proof reveals which registration it is for; it is compact, not anonymous.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MembershipProof {
+    pub y1: BigUint,
+    pub y2: BigUint,
+    pub path: MerklePath,
+    pub proof: DleqProof,
+}
+
+impl ZKP {
+    /// Encodes a registration as a Merkle leaf.
+    pub fn registration_leaf(y1: &BigUint, y2: &BigUint) -> Vec<u8> {
+        let mut leaf = Vec::new();
+        write_biguint(&mut leaf, y1);
+        write_biguint(&mut leaf, y2);
+        leaf
+    }
+
+    /// Proves membership of the registration of `x` under `root`.
+    ///
+    /// # Arguments
+    /// * `x` - The prover's secret.
+    /// * `path` - The inclusion path of the prover's registration.
+    /// * `root` - The tree root the proof is bound to.
+    /// * `context` - A verifier-chosen value (a nonce) preventing replays.
+    pub fn prove_membership(&self, x: &BigUint, path: MerklePath, root: &[u8], context: &[u8]) -> MembershipProof {
+        let (y1, y2) = self.compute_pair(x);
+        let proof = self.prove_dleq_bound(x, &membership_binding(root, context));
+        MembershipProof { y1, y2, path, proof }
+    }
+
+    /// Verifies a membership proof against `root` for the given `context`.
+    pub fn verify_membership<H: MerkleHasher>(
+        &self,
+        hasher: &H,
+        root: &[u8],
+        context: &[u8],
+        proof: &MembershipProof,
+    ) -> bool {
+        let leaf = ZKP::registration_leaf(&proof.y1, &proof.y2);
+        MerkleTree::verify_path(hasher, root, &leaf, &proof.path)
+            && self.verify_dleq_bound(&proof.y1, &proof.y2, &proof.proof, &membership_binding(root, context))
+    }
+}
+
+/// Encodes the root and context that a membership proof is bound to.
+fn membership_binding(root: &[u8], context: &[u8]) -> Vec<u8> {
+    let mut binding = Vec::new();
+    for part in [root, context] {
+        binding.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        binding.extend_from_slice(part);
+    }
+    binding
+}
+
+/// One cut-and-choose round of a `ShuffleProof`.
+///
+/// `intermediate` is a fresh shuffle of the input. Depending on the challenge bit,
+/// `permutation` and `randomness` open either input → intermediate or
+/// intermediate → output, never both.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShuffleRound {
+    pub intermediate: Vec<Ciphertext>,
+    pub permutation: Vec<usize>,
+    pub randomness: Vec<BigUint>,
+}
+
+/// Proof that a list of ElGamal ciphertexts is a re-encrypted permutation of another.
+///
+/// This is the Sako–Kilian cut-and-choose proof made non-interactive with one
+/// challenge bit per round: its soundness error is `2^-rounds`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShuffleProof {
+    pub rounds: Vec<ShuffleRound>,
+}
+
+/// The fewest rounds `verify_shuffle` accepts. A non-interactive proof can be
+/// ground offline, so the soundness error `2^-rounds` has to be negligible.
+pub const MIN_SHUFFLE_ROUNDS: usize = 128;
+
+impl ZKP {
+    /// Re-randomizes a ciphertext under `y` with the randomness `s`.
+    pub fn reencrypt(&self, y: &BigUint, ciphertext: &Ciphertext, s: &BigUint) -> Ciphertext {
+        Ciphertext {
+            c1: &ciphertext.c1 * self.alpha.modpow(s, &self.p) % &self.p,
+            c2: &ciphertext.c2 * y.modpow(s, &self.p) % &self.p,
+        }
+    }
+
+    /// Shuffles and re-encrypts `input` under `y`.
+    ///
+    /// # Returns
+    /// The output, with `output[i] = reencrypt(input[permutation[i]], randomness[i])`,
+    /// together with the permutation and randomness needed by `prove_shuffle`.
+    pub fn shuffle(&self, y: &BigUint, input: &[Ciphertext]) -> (Vec<Ciphertext>, Vec<usize>, Vec<BigUint>) {
+        let mut permutation: Vec<usize> = (0..input.len()).collect();
+        permutation.shuffle(&mut OsRng);
+        let randomness: Vec<BigUint> = input.iter().map(|_| ZKP::generate_random_below(&self.q)).collect();
+        let output = permutation
+            .iter()
+            .zip(&randomness)
+            .map(|(&j, s)| self.reencrypt(y, &input[j], s))
+            .collect();
+        (output, permutation, randomness)
+    }
+
+    /// Proves that `output` was produced from `input` by `shuffle`.
+    ///
+    /// # Arguments
+    /// * `y` - The public key the ciphertexts are encrypted under.
+    /// * `input`, `output` - The ciphertexts before and after the shuffle.
+    /// * `permutation`, `randomness` - The secrets returned by `shuffle`.
+    /// * `rounds` - The number of cut-and-choose rounds, at least `MIN_SHUFFLE_ROUNDS`
+    ///   for the proof to verify.
+    pub fn prove_shuffle(
+        &self,
+        y: &BigUint,
+        input: &[Ciphertext],
+        output: &[Ciphertext],
+        permutation: &[usize],
+        randomness: &[BigUint],
+        rounds: usize,
+    ) -> ShuffleProof {
+        // Commit to a fresh shuffle of the input for every round.
+        let commitments: Vec<(Vec<Ciphertext>, Vec<usize>, Vec<BigUint>)> =
+            (0..rounds).map(|_| self.shuffle(y, input)).collect();
+        let intermediates: Vec<&[Ciphertext]> = commitments.iter().map(|(c, _, _)| c.as_slice()).collect();
+        let bits = self.shuffle_challenge(y, input, output, &intermediates);
+
+        let rounds = commitments
+            .into_iter()
+            .zip(bits)
+            .map(|((intermediate, pi, t), bit)| {
+                if !bit {
+                    return ShuffleRound { intermediate, permutation: pi, randomness: t };
+                }
+                // Open intermediate -> output: output[i] comes from intermediate[sigma[i]].
+                let mut inverse = vec![0; pi.len()];
+                for (position, &j) in pi.iter().enumerate() {
+                    inverse[j] = position;
+                }
+                let sigma: Vec<usize> = permutation.iter().map(|&j| inverse[j]).collect();
+                let u = sigma
+                    .iter()
+                    .zip(randomness)
+                    .map(|(&k, r)| (r + &self.q - &t[k]) % &self.q)
+                    .collect();
+                ShuffleRound { intermediate, permutation: sigma, randomness: u }
+            })
+            .collect();
+        ShuffleProof { rounds }
+    }
+
+    /// Verifies that `output` is a re-encrypted permutation of `input` under `y`.
+    ///
+    /// Proofs with fewer than `MIN_SHUFFLE_ROUNDS` rounds, and ciphertexts outside
+    /// the order-q subgroup, are rejected.
+    pub fn verify_shuffle(
+        &self,
+        y: &BigUint,
+        input: &[Ciphertext],
+        output: &[Ciphertext],
+        proof: &ShuffleProof,
+        rounds: usize,
+    ) -> bool {
+        if rounds < MIN_SHUFFLE_ROUNDS || input.len() != output.len() || proof.rounds.len() != rounds {
+            return false;
+        }
+        if !input.iter().chain(output).all(|c| self.in_subgroup(&c.c1) && self.in_subgroup(&c.c2)) {
+            return false;
+        }
+        let intermediates: Vec<&[Ciphertext]> = proof.rounds.iter().map(|r| r.intermediate.as_slice()).collect();
+        let bits = self.shuffle_challenge(y, input, output, &intermediates);
+
+        proof.rounds.iter().zip(bits).all(|(round, bit)| {
+            let (from, to) = if bit {
+                (round.intermediate.as_slice(), output)
+            } else {
+                (input, round.intermediate.as_slice())
+            };
+            round.intermediate.len() == input.len()
+                && round.randomness.len() == input.len()
+                && is_permutation(&round.permutation, input.len())
+                && to.iter().zip(&round.permutation).zip(&round.randomness).all(|((target, &j), s)| {
+                    *s < self.q && self.reencrypt(y, &from[j], s) == *target
+                })
+        })
+    }
+
+    /// Derives one challenge bit per round from the whole statement and commitments.
+    fn shuffle_challenge(
+        &self,
+        y: &BigUint,
+        input: &[Ciphertext],
+        output: &[Ciphertext],
+        intermediates: &[&[Ciphertext]],
+    ) -> Vec<bool> {
+        let mut elements = vec![y];
+        for ciphertexts in [input, output].into_iter().chain(intermediates.iter().copied()) {
+            elements.extend(ciphertexts.iter().flat_map(|c| [&c.c1, &c.c2]));
+        }
+        let digest = self.hash_to_challenge(b"zkp-shuffle", &elements).to_bytes_be();
+
+        let mut bits = Vec::with_capacity(intermediates.len());
+        for block in 0u32.. {
+            let hash = Sha256::new().chain_update(&digest).chain_update(block.to_be_bytes()).finalize();
+            for byte in hash {
+                for shift in 0..8 {
+                    if bits.len() == intermediates.len() {
+                        return bits;
+                    }
+                    bits.push(byte >> shift & 1 == 1);
+                }
+            }
+        }
+        bits
+    }
+}
+
+/// Checks that `permutation` is a permutation of `0..n`.
+fn is_permutation(permutation: &[usize], n: usize) -> bool {
+    let mut seen = vec![false; n];
+    permutation.len() == n
+        && permutation
+            .iter()
+            .all(|&j| j < n && !std::mem::replace(&mut seen[j], true))
+}
+
+/// Disjunctive Chaum–Pedersen proof that an exponential ElGamal ciphertext
+/// encrypts 0 or 1.
+///
+/// Branch `j` proves `log_alpha(c1) == log_y(c2 / alpha^j)`; the commitments of both
+/// branches are recomputed from the challenges and responses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BallotProof {
+    pub e0: BigUint,
+    pub e1: BigUint,
+    pub s0: BigUint,
+    pub s1: BigUint,
+}
+
+/// An encrypted yes/no vote together with its proof of validity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ballot {
+    pub ciphertext: Ciphertext,
+    pub proof: BallotProof,
+}
+
+/// A trustee's share of a threshold decryption, `d = c1^x_i`, with a proof that it
+/// was computed with the share behind the trustee's verification key `alpha^x_i`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecryptionShare {
+    pub index: u32,
+    pub d: BigUint,
+    pub proof: DleqProof,
+}
+
+impl ZKP {
+    /// Encrypts a yes/no vote under the election key `y` and proves it is 0 or 1.
+    ///
+    /// The proof is bound to `election_id` and `voter_id`, so it cannot be replayed
+    /// in another election or under another voter's name.
+    pub fn cast_ballot(&self, y: &BigUint, election_id: &[u8], voter_id: &[u8], vote: bool) -> Ballot {
+        let r = ZKP::generate_random_below(&self.q);
+        let v = BigUint::from(vote as u32);
+        let ciphertext = Ciphertext {
+            c1: self.alpha.modpow(&r, &self.p),
+            c2: self.alpha.modpow(&v, &self.p) * y.modpow(&r, &self.p) % &self.p,
+        };
+        let group = self.with_beta(y);
+        let targets = self.ballot_targets(&ciphertext);
+        let real = vote as usize;
+        let fake = 1 - real;
+
+        // Simulate the branch for the vote we did not cast.
+        let mut e = [BigUint::from(0u32), BigUint::from(0u32)];
+        let mut s = [BigUint::from(0u32), BigUint::from(0u32)];
+        e[fake] = ZKP::generate_random_below(&self.q);
+        s[fake] = ZKP::generate_random_below(&self.q);
+        let mut commitments = [
+            self.ballot_commitment(&group, &ciphertext.c1, &targets[0], &e[0], &s[0]),
+            self.ballot_commitment(&group, &ciphertext.c1, &targets[1], &e[1], &s[1]),
+        ];
+
+        // Answer the real branch with the challenge left over from the hash.
+        let k = ZKP::generate_random_below(&self.q);
+        commitments[real] = group.compute_pair(&k);
+        let context = ballot_context(election_id, voter_id);
+        let challenge = self.ballot_challenge(y, &context, &ciphertext, &commitments);
+        e[real] = (challenge + &self.q - &e[fake]) % &self.q;
+        s[real] = self.response(&k, &e[real], &r);
+
+        let [e0, e1] = e;
+        let [s0, s1] = s;
+        Ballot { ciphertext, proof: BallotProof { e0, e1, s0, s1 } }
+    }
+
+    /// Verifies that a ballot cast by `voter_id` in `election_id` encrypts 0 or 1
+    /// under the election key `y`. Both ciphertext components must lie in the
+    /// order-q subgroup; otherwise the branch relations say nothing about the vote.
+    pub fn verify_ballot(&self, y: &BigUint, election_id: &[u8], voter_id: &[u8], ballot: &Ballot) -> bool {
+        if !self.in_subgroup(&ballot.ciphertext.c1) || !self.in_subgroup(&ballot.ciphertext.c2) {
+            return false;
+        }
+        let group = self.with_beta(y);
+        let targets = self.ballot_targets(&ballot.ciphertext);
+        let proof = &ballot.proof;
+        let commitments = [
+            self.ballot_commitment(&group, &ballot.ciphertext.c1, &targets[0], &proof.e0, &proof.s0),
+            self.ballot_commitment(&group, &ballot.ciphertext.c1, &targets[1], &proof.e1, &proof.s1),
+        ];
+        let context = ballot_context(election_id, voter_id);
+        let challenge = self.ballot_challenge(y, &context, &ballot.ciphertext, &commitments);
+        (&proof.e0 + &proof.e1) % &self.q == challenge
+    }
+
+    /// Multiplies the ballots into one ciphertext encrypting the number of yes votes.
+    pub fn tally_ballots(&self, ballots: &[Ballot]) -> Ciphertext {
+        let empty = Ciphertext { c1: BigUint::from(1u32), c2: BigUint::from(1u32) };
+        ballots
+            .iter()
+            .fold(empty, |tally, ballot| self.add_ciphertexts(&tally, &ballot.ciphertext))
+    }
+
+    /// Computes a trustee's decryption share of `ciphertext` with its key share.
+    pub fn decryption_share(&self, share: &SecretShare, ciphertext: &Ciphertext) -> DecryptionShare {
+        DecryptionShare {
+            index: share.index,
+            d: ciphertext.c1.modpow(&share.value, &self.p),
+            proof: self.with_beta(&ciphertext.c1).prove_dleq(&share.value),
+        }
+    }
+
+    /// Verifies a decryption share against the trustee's verification key `alpha^x_i`.
+    ///
+    /// The key, `c1` and `d` must be non-identity elements of the order-`q` subgroup;
+    /// otherwise a negated share `p - d` passes whenever the challenge is even.
+    pub fn verify_decryption_share(
+        &self,
+        verification_key: &BigUint,
+        ciphertext: &Ciphertext,
+        share: &DecryptionShare,
+    ) -> bool {
+        self.in_subgroup(verification_key)
+            && self.in_subgroup(&ciphertext.c1)
+            && self.in_subgroup(&share.d)
+            && self.with_beta(&ciphertext.c1).verify_dleq(verification_key, &share.d, &share.proof)
+    }
+
+    /// Combines threshold-many verified decryption shares of an exponential
+    /// ElGamal ciphertext.
+    ///
+    /// # Returns
+    /// The plaintext, or `None` if it is larger than `max` or the share indices
+    /// are not distinct and non-zero.
+    pub fn combine_decryption_shares(&self, ciphertext: &Ciphertext, shares: &[DecryptionShare], max: u64) -> Option<u64> {
+        let indices: Vec<u32> = shares.iter().map(|share| share.index).collect();
+        if !distinct_indices(&indices) {
+            return None;
+        }
+        let shared = shares.iter().fold(BigUint::from(1u32), |acc, share| {
+            let lambda = self.lagrange_coefficient(share.index, &indices);
+            acc * share.d.modpow(&lambda, &self.p) % &self.p
+        });
+        let m = &ciphertext.c2 * self.inverse(&shared) % &self.p;
+        self.discrete_log(&m, max)
+    }
+
+    /// Returns `c2` and `c2 / alpha`, the `y`-side targets of the two ballot branches.
+    fn ballot_targets(&self, ciphertext: &Ciphertext) -> [BigUint; 2] {
+        let shifted = &ciphertext.c2 * self.inverse(&self.alpha) % &self.p;
+        [ciphertext.c2.clone(), shifted]
+    }
+
+    /// Recomputes a branch commitment `(alpha^s * c1^e, y^s * target^e)`.
+    fn ballot_commitment(
+        &self,
+        group: &ZKP,
+        c1: &BigUint,
+        target: &BigUint,
+        e: &BigUint,
+        s: &BigUint,
+    ) -> (BigUint, BigUint) {
+        let (a, b) = group.compute_pair(s);
+        (a * c1.modpow(e, &self.p) % &self.p, b * target.modpow(e, &self.p) % &self.p)
+    }
+
+    fn ballot_challenge(
+        &self,
+        y: &BigUint,
+        context: &BigUint,
+        ciphertext: &Ciphertext,
+        commitments: &[(BigUint, BigUint); 2],
+    ) -> BigUint {
+        let [(a0, b0), (a1, b1)] = commitments;
+        self.hash_to_challenge(b"zkp-ballot", &[y, context, &ciphertext.c1, &ciphertext.c2, a0, b0, a1, b1])
+    }
+}
+
+/// Encodes the election and voter a ballot proof is bound to.
+fn ballot_context(election_id: &[u8], voter_id: &[u8]) -> BigUint {
+    let mut context = Vec::new();
+    write_bytes(&mut context, election_id);
+    write_bytes(&mut context, voter_id);
+    message_digest(&context)
+}
+
+/// The broadcast part of a participant's dealing in the distributed key generation:
+/// Feldman commitments `alpha^a_j` to the coefficients of its secret polynomial.
+///
+/// The matching shares are sent to each participant over a private channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DkgDealing {
+    pub dealer: u32,
+    pub commitments: Vec<BigUint>,
+}
+
+/// A complaint by `accuser` that the share it received from `dealer` is invalid.
+///
+/// The dealer answers by broadcasting the share in the clear; a dealer that does not
+/// answer, or answers with another invalid share, is disqualified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Complaint {
+    pub accuser: u32,
+    pub dealer: u32,
+}
+
+impl ZKP {
+    /// Splits `secret` like `split_secret` and publishes Feldman commitments
+    /// to the polynomial so every share can be verified.
+    ///
+    /// # Returns
+    /// The commitments `alpha^a_j mod p` and shares with indices `1..=n`, or `None`
+    /// unless `1 <= threshold <= n`.
+    pub fn feldman_deal(&self, secret: &BigUint, threshold: usize, n: usize) -> Option<(Vec<BigUint>, Vec<SecretShare>)> {
+        let (coefficients, shares) = self.share_polynomial(secret, threshold, n)?;
+        let commitments = coefficients.iter().map(|a| self.alpha.modpow(a, &self.p)).collect();
+        Some((commitments, shares))
+    }
+
+    /// Checks a share against the dealer's Feldman commitments.
+    ///
+    /// # Returns
+    /// `true` if `alpha^value == prod A_j^(index^j) mod p`, otherwise `false`.
+    pub fn feldman_verify_share(&self, commitments: &[BigUint], share: &SecretShare) -> bool {
+        share.value < self.q && self.alpha.modpow(&share.value, &self.p) == self.feldman_public_share(commitments, share.index)
+    }
+
+    /// Deals a random secret as participant `dealer` of a distributed key generation.
+    pub fn dkg_deal(&self, dealer: u32, threshold: usize, n: usize) -> Option<(DkgDealing, Vec<SecretShare>)> {
+        let secret = ZKP::generate_random_below(&self.q);
+        let (commitments, shares) = self.feldman_deal(&secret, threshold, n)?;
+        Some((DkgDealing { dealer, commitments }, shares))
+    }
+
+    /// Checks the share `recipient` received from a dealing, returning a complaint
+    /// if it does not match the dealer's commitments.
+    pub fn dkg_check_share(&self, dealing: &DkgDealing, recipient: u32, share: &SecretShare) -> Option<Complaint> {
+        let valid = share.index == recipient && self.feldman_verify_share(&dealing.commitments, share);
+        (!valid).then_some(Complaint { accuser: recipient, dealer: dealing.dealer })
+    }
+
+    /// Determines the qualified dealers after the complaint round.
+    ///
+    /// # Arguments
+    /// * `dealings` - The broadcast dealings of all participants.
+    /// * `complaints` - The complaints filed against dealers.
+    /// * `answers` - The shares dealers revealed in answer to complaints, keyed by dealer.
+    /// * `threshold` - The number of participants needed to use the key.
+    ///
+    /// # Returns
+    /// The indices of the dealers whose dealings are well-formed and who answered
+    /// every complaint with a valid share.
+    pub fn dkg_qualified(
+        &self,
+        dealings: &[DkgDealing],
+        complaints: &[Complaint],
+        answers: &[(u32, SecretShare)],
+        threshold: usize,
+    ) -> Vec<u32> {
+        dealings
+            .iter()
+            .filter(|dealing| dealing.commitments.len() == threshold)
+            .filter(|dealing| {
+                complaints
+                    .iter()
+                    .filter(|complaint| complaint.dealer == dealing.dealer)
+                    .all(|complaint| {
+                        answers.iter().any(|(dealer, share)| {
+                            *dealer == dealing.dealer
+                                && share.index == complaint.accuser
+                                && self.feldman_verify_share(&dealing.commitments, share)
+                        })
+                    })
+            })
+            .map(|dealing| dealing.dealer)
+            .collect()
+    }
+
+    /// Computes the joint public key `prod A_0` over the qualified dealings.
+    pub fn dkg_public_key(&self, dealings: &[DkgDealing], qualified: &[u32]) -> BigUint {
+        self.qualified_dealings(dealings, qualified)
+            .fold(BigUint::from(1u32), |acc, dealing| acc * &dealing.commitments[0] % &self.p)
+    }
+
+    /// Computes the verification key `alpha^x_i` of participant `index`, used to
+    /// check its decryption shares.
+    pub fn dkg_verification_key(&self, dealings: &[DkgDealing], qualified: &[u32], index: u32) -> BigUint {
+        self.qualified_dealings(dealings, qualified).fold(BigUint::from(1u32), |acc, dealing| {
+            acc * self.feldman_public_share(&dealing.commitments, index) % &self.p
+        })
+    }
+
+    /// Sums the shares a participant received from the qualified dealers into its
+    /// share of the joint secret.
+    pub fn dkg_combine_shares(&self, index: u32, shares: &[SecretShare]) -> SecretShare {
+        let value = shares.iter().fold(BigUint::from(0u32), |acc, share| (acc + &share.value) % &self.q);
+        SecretShare { index, value }
+    }
+
+    /// Computes `prod A_j^(index^j) mod p`, the public image of the share at `index`.
+    fn feldman_public_share(&self, commitments: &[BigUint], index: u32) -> BigUint {
+        let i = BigUint::from(index);
+        let mut power = BigUint::from(1u32);
+        let mut result = BigUint::from(1u32);
+        for commitment in commitments {
+            result = result * commitment.modpow(&power, &self.p) % &self.p;
+            power = power * &i % &self.q;
+        }
+        result
+    }
+
+    fn qualified_dealings<'a>(&self, dealings: &'a [DkgDealing], qualified: &'a [u32]) -> impl Iterator<Item = &'a DkgDealing> {
+        dealings.iter().filter(move |dealing| qualified.contains(&dealing.dealer))
+    }
+}
+
+/// The public transcript of one login, which the session key is bound to.
+#[derive(Debug, Clone, Copy)]
+pub struct LoginTranscript<'a> {
+    pub user: &'a str,
+    pub auth_id: &'a str,
+    pub r1: &'a BigUint,
+    pub r2: &'a BigUint,
+    pub server_share: &'a BigUint,
+    pub c: &'a BigUint,
+    pub s: &'a BigUint,
+}
+
+impl ZKP {
+    /// Derives the session key of a login from the Diffie–Hellman secret between
+    /// the client's commitment `r1 = alpha^k` and the server's share `alpha^b`.
+    ///
+    /// The client computes the secret as `server_share^k` and the server as `r1^b`.
+    /// Only the prover who knows `k` can compute it, and the proof binds `k` to the
+    /// login secret, so a relayed login does not yield the key. The server's share
+    /// is not authenticated by this protocol; pin the server with TLS.
+    pub fn derive_session_key(&self, shared: &BigUint, transcript: &LoginTranscript) -> [u8; 32] {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&shared.to_bytes_be()).expect("HMAC accepts any key length");
+        mac.update(b"zkp-session-key");
+        let mut encoded = Vec::new();
+        for text in [transcript.user, transcript.auth_id] {
+            encoded.extend_from_slice(&(text.len() as u32).to_be_bytes());
+            encoded.extend_from_slice(text.as_bytes());
+        }
+        for n in [transcript.r1, transcript.r2, transcript.server_share, transcript.c, transcript.s] {
+            write_biguint(&mut encoded, n);
+        }
+        mac.update(&encoded);
+        mac.finalize().into_bytes().into()
+    }
+}
+
+impl ZKP {
+    /// Binds a credential rotation to the new keys: the prover answers
+    /// `H(c, y1, y2, salt)` instead of the server's challenge `c`, so an answer
+    /// captured on the wire cannot install other keys.
+    pub fn rotation_challenge(&self, c: &BigUint, y1: &BigUint, y2: &BigUint, salt: &[u8]) -> BigUint {
+        self.hash_to_challenge(b"zkp-rotation", &[c, y1, y2, &message_digest(salt)])
+    }
+}
+
+/// Computes an HMAC-SHA256 tag over `data` with a session key.
+pub fn session_mac(key: &[u8; 32], data: &[u8]) -> [u8; 32] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// Encodes the data a request MAC covers: the gRPC method name and the encoded
+/// request message, each length-prefixed.
+pub fn request_mac_data(method: &str, message: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    write_bytes(&mut data, method.as_bytes());
+    write_bytes(&mut data, message);
+    data
+}
+
+/// Checks an HMAC-SHA256 tag in constant time.
+pub fn verify_session_mac(key: &[u8; 32], data: &[u8], tag: &[u8]) -> bool {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.verify_slice(tag).is_ok()
+}
+
+/// Hashes an arbitrary byte string into a fixed-width integer.
+fn message_digest(message: &[u8]) -> BigUint {
+    BigUint::from_bytes_be(&Sha256::digest(message))
+}
+
+/// Appends `n` as a big-endian `u32` length followed by its big-endian bytes.
+fn write_biguint(out: &mut Vec<u8>, n: &BigUint) {
+    write_bytes(out, &n.to_bytes_be());
+}
+
+/// Appends `bytes` to `out`, prefixed with its length as a big-endian `u32`.
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+/// Reads a big-endian `u32` from the front of `input`.
+fn read_u32(input: &mut &[u8]) -> Option<u32> {
+    if input.len() < 4 {
+        return None;
+    }
+    let (head, rest) = input.split_at(4);
+    *input = rest;
+    Some(u32::from_be_bytes(head.try_into().ok()?))
+}
+
+/// Reads an integer written by `write_biguint` from the front of `input`.
+fn read_biguint(input: &mut &[u8]) -> Option<BigUint> {
+    read_bytes(input).map(BigUint::from_bytes_be)
+}
+
+/// Reads a byte string written by `write_bytes` from the front of `input`.
+fn read_bytes<'a>(input: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = read_u32(input)? as usize;
+    if input.len() < len {
+        return None;
+    }
+    let (head, rest) = input.split_at(len);
+    *input = rest;
+    Some(head)
+}
+#[cfg(test)]
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Tests ZKP functionality with small, predefined values.
+    #[test]
+    fn test_toy_example_with_random_numbers() {
+        // Initialize the ZKP struct with small, predefined values.
+        let zkp = ZKP {
+            p: BigUint::from(23u32),
+            q: BigUint::from(11u32),
+            alpha: BigUint::from(4u32),
+            beta: BigUint::from(9u32),
+        };
+
+        // Generate random values for private key (x), nonce (k), and challenge (c).
+        let x = ZKP::generate_random_below(&zkp.q);
+        let k = ZKP::generate_random_below(&zkp.q);
+        let c = ZKP::generate_random_below(&zkp.q);
+
+        // Compute public keys (y1, y2) and commitments (r1, r2).
+        let y1 = ZKP::exponentiate(&zkp.alpha, &x, &zkp.p);
+        let y2 = ZKP::exponentiate(&zkp.beta, &x, &zkp.p);
+        let r1 = ZKP::exponentiate(&zkp.alpha, &k, &zkp.p);
+        let r2 = ZKP::exponentiate(&zkp.beta, &k, &zkp.p);
+
+        // Compute response and verify the proof.
+        let s = zkp.response(&k, &c, &x);
+        let result = zkp.verify(&r1, &r2, &y1, &y2, &c, &s);
+
+        // Assert the proof is valid.
+        assert!(result);
+    }
+
+    /// Tests ZKP functionality using 1024-bit constants from RFC 5114.
+    #[test]
+    fn test_with_1024_bit_constant() {
+        // Parse hexadecimal constants for p, alpha, and q as per RFC 5114.
+        let p_str = "B10B8F96A080E01DDE92DE5EAE5D54EC52C99FBCFB06A3C69A6A9DCA52D23B616073E28675A23D189838EF1E2EE652C013ECB4AEA906112324975C3CD49B83BFACCBDD7D90C4BD7098488E9C219A73724EFFD6FAE5644738FAA31A4FF55BCCC0A151AF5F0DC8B4BD45BF37DF365C1A65E68CFDA76D4DA708DF1FB2BC2E4A4371";
+        let q_str = "F518AA8781A8DF278ABA4E7D64B7CB9D49462353";
+        let g_str = "A4D1CBD5C3FD34126765A442EFB99905F8104DD258AC507FD6406CFF14266D31266FEA1E5C41564B777E690F5504F213160217B4B01B886A5E91547F9E2749F4D7FBD7D3B9A92EE1909D0D2263F80A76A6A24C087A091F531DBF0A0169B6A28AD662A4D18E73AFA32D779D5918D08BC8858F4DCEF97C2A24855E6EEB22B3B2E5";
+        //Convert strings to BigUint.
+        let p = BigUint::parse_bytes(p_str.as_bytes(), 16).unwrap();
+        let alpha = BigUint::parse_bytes(g_str.as_bytes(), 16).unwrap();
+        let q = BigUint::parse_bytes(q_str.as_bytes(), 16).unwrap();
+
+        // Compute beta as alpha raised to a random exponent modulo p.
+        let beta = alpha.modpow(&ZKP::generate_random_below(&q), &p);
+
+        // Initialize ZKP with the large constants.
+        let zkp = ZKP { p, q, alpha, beta };
+
+        // Generate random values for private key (x), nonce (k), and challenge (c).
+        let x = ZKP::generate_random_below(&zkp.q);
+        let k = ZKP::generate_random_below(&zkp.q);
+        let c = ZKP::generate_random_below(&zkp.q);
+
+        // Compute public keys (y1, y2) and commitments (r1, r2).
+        let y1 = ZKP::exponentiate(&zkp.alpha, &x, &zkp.p);
+        let y2 = ZKP::exponentiate(&zkp.beta, &x, &zkp.p);
+        let r1 = ZKP::exponentiate(&zkp.alpha, &k, &zkp.p);
+        let r2 = ZKP::exponentiate(&zkp.beta, &k, &zkp.p);
+
+        // Compute response and verify the proof.
+        let s = zkp.response(&k, &c, &x);
+        let result = zkp.verify(&r1, &r2, &y1, &y2, &c, &s);
+
+        // Assert the proof is valid.
+        assert!(result);
+    }
+
+    /// Tests that Pedersen commitments open correctly and add homomorphically.
+    #[test]
+    fn test_pedersen_commitment_homomorphic_addition() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+
+        // Commit to two values under independent blinding factors.
+        let m1 = ZKP::generate_random_below(&zkp.q);
+        let r1 = ZKP::generate_random_below(&zkp.q);
+        let m2 = ZKP::generate_random_below(&zkp.q);
+        let r2 = ZKP::generate_random_below(&zkp.q);
+        let c1 = zkp.commit(&m1, &r1);
+        let c2 = zkp.commit(&m2, &r2);
+
+        assert!(zkp.open(&c1, &m1, &r1));
+        assert!(!zkp.open(&c1, &m2, &r1));
+
+        // The product of the commitments opens to the sums of values and blindings.
+        let sum = zkp.add_commitments(&c1, &c2);
+        let m = (&m1 + &m2) % &zkp.q;
+        let r = (&r1 + &r2) % &zkp.q;
+        assert!(zkp.open(&sum, &m, &r));
+
+        // The blinding base is not beta, whose exponent in `get_constants` is public.
+        let h = zkp.pedersen_base();
+        assert_ne!(h, zkp.beta);
+        assert!(zkp.in_subgroup(&h));
+
+        // Knowing log_alpha(beta) would open c1 to m2 with r1 + (m1 - m2) / exp;
+        // against the hashed base that opening fails.
+        let exp = BigUint::parse_bytes(b"A4D1CBD5504F213160217B46A5E90", 16).unwrap();
+        let shift = (&m1 + &zkp.q - &m2) % &zkp.q * zkp.inverse_mod_q(&exp) % &zkp.q;
+        assert!(!zkp.open(&c1, &m2, &((&r1 + shift) % &zkp.q)));
+    }
+
+    /// Tests the proof-of-opening sigma protocol for a Pedersen commitment.
+    #[test]
+    fn test_pedersen_proof_of_opening() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+
+        let m = ZKP::generate_random_below(&zkp.q);
+        let r = ZKP::generate_random_below(&zkp.q);
+        let commitment = zkp.commit(&m, &r);
+
+        // Prover commits to random nonces, verifier picks a challenge.
+        let k1 = ZKP::generate_random_below(&zkp.q);
+        let k2 = ZKP::generate_random_below(&zkp.q);
+        let t = zkp.commit(&k1, &k2).c;
+        let c = ZKP::generate_random_below(&zkp.q);
+
+        // Compute responses and verify the proof.
+        let s1 = zkp.response(&k1, &c, &m);
+        let s2 = zkp.response(&k2, &c, &r);
+        assert!(zkp.verify_opening(&commitment, &t, &c, &s1, &s2));
+
+        // A response computed for a different value must be rejected.
+        let wrong = zkp.response(&k1, &c, &(&m + 1u32));
+        assert!(!zkp.verify_opening(&commitment, &t, &c, &wrong, &s2));
+    }
+
+    /// Tests range proofs for an upper-bounded and a lower-bounded value.
+    #[test]
+    fn test_range_proof() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+
+        // Prove that a committed balance lies in [0, 2^32).
+        let balance = BigUint::from(1_000_000u32);
+        let r = ZKP::generate_random_below(&zkp.q);
+        let commitment = zkp.commit(&balance, &r);
+        let proof = zkp.prove_range(&balance, &r, 32).unwrap();
+        assert!(zkp.verify_range(&commitment, &proof, 32));
+        assert!(!zkp.verify_range(&commitment, &proof, 16));
+
+        // The proof must survive a round trip through its byte encoding.
+        let decoded = RangeProof::from_bytes(&proof.to_bytes()).unwrap();
+        assert_eq!(decoded, proof);
+        assert!(RangeProof::from_bytes(&proof.to_bytes()[1..]).is_none());
+
+        // Prove age >= 18 by proving age - 18 is in range against the shifted commitment.
+        let age = BigUint::from(42u32);
+        let minimum = BigUint::from(18u32);
+        let commitment = zkp.commit(&age, &r);
+        let proof = zkp.prove_range(&(&age - &minimum), &r, 8).unwrap();
+        assert!(zkp.verify_range(&zkp.offset_commitment(&commitment, &minimum), &proof, 8));
+
+        // A proof for a different value does not verify against this commitment.
+        let other = zkp.prove_range(&BigUint::from(7u32), &r, 8).unwrap();
+        assert!(!zkp.verify_range(&zkp.offset_commitment(&commitment, &minimum), &other, 8));
+
+        // Values that do not fit in the range cannot be proven.
+        assert!(zkp.prove_range(&BigUint::from(256u32), &r, 8).is_none());
+
+        // The widest range is one bit short of q; from q's bit length on the
+        // bit sum could wrap, so neither side accepts it.
+        let widest = zkp.q.bits() as usize - 1;
+        let proof = zkp.prove_range(&balance, &r, widest).unwrap();
+        assert!(zkp.verify_range(&zkp.commit(&balance, &r), &proof, widest));
+        assert!(zkp.prove_range(&balance, &r, widest + 1).is_none());
+        let mut wide = proof.clone();
+        wide.bits.push(proof.bits[0].clone());
+        assert!(!zkp.verify_range(&zkp.commit(&balance, &r), &wide, widest + 1));
+    }
+
+    /// Tests Schnorr signatures made with the login secret.
+    #[test]
+    fn test_schnorr_signature() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+
+        // The signing key is the same secret the client logs in with.
+        let x = ZKP::generate_random_below(&zkp.q);
+        let (y1, _) = zkp.compute_pair(&x);
+
+        let sig = zkp.sign(b"GET /accounts/42", &x);
+        assert!(zkp.verify_signature(b"GET /accounts/42", &y1, &sig));
+        assert!(!zkp.verify_signature(b"GET /accounts/43", &y1, &sig));
+
+        // Signatures round trip through their byte encoding.
+        let decoded = SchnorrSignature::from_bytes(&sig.to_bytes()).unwrap();
+        assert_eq!(decoded, sig);
+
+        // A signature does not verify under another key.
+        let other = ZKP::generate_random_below(&zkp.q);
+        let (other_y1, _) = zkp.compute_pair(&other);
+        assert!(!zkp.verify_signature(b"GET /accounts/42", &other_y1, &sig));
+    }
+
+    /// Tests a three-party MuSig signature through all rounds.
+    #[test]
+    fn test_musig_multi_signature() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let message = b"approve transfer #7";
+
+        // Key aggregation.
+        let secrets: Vec<BigUint> = (0..3).map(|_| ZKP::generate_random_below(&zkp.q)).collect();
+        let keys: Vec<BigUint> = secrets.iter().map(|x| zkp.compute_pair(x).0).collect();
+        let group = zkp.aggregate_keys(&keys);
+
+        // Nonce commitment round, then the nonces are revealed and checked.
+        let nonces: Vec<MuSigNonce> = secrets.iter().map(|_| zkp.musig_nonce()).collect();
+        let revealed: Vec<BigUint> = nonces.iter().map(|n| n.r.clone()).collect();
+        let commitments: Vec<BigUint> = revealed.iter().map(|r| zkp.musig_nonce_commitment(r)).collect();
+        let r = zkp.aggregate_nonces(&revealed, &commitments).unwrap();
+
+        // A nonce swapped after the commitment round is rejected.
+        let mut swapped = revealed.clone();
+        swapped[0] = zkp.musig_nonce().r;
+        assert!(zkp.aggregate_nonces(&swapped, &commitments).is_none());
+
+        // Partial signatures are individually verifiable and aggregate into one signature.
+        let mut partials = Vec::new();
+        for ((x, nonce), (y, r_i)) in secrets.iter().zip(nonces).zip(keys.iter().zip(&revealed)) {
+            let s_i = zkp.musig_partial_sign(&group, nonce, &r, x, message).unwrap();
+            assert!(zkp.musig_verify_partial(&group, y, r_i, &r, message, &s_i));
+            partials.push(s_i);
+        }
+        let sig = zkp.musig_aggregate(&r, &partials);
+        assert!(zkp.verify_signature(message, &group.key, &sig));
+        assert!(!zkp.verify_signature(b"approve transfer #8", &group.key, &sig));
+
+        // Dropping a signer's contribution invalidates the signature.
+        let sig = zkp.musig_aggregate(&r, &partials[1..]);
+        assert!(!zkp.verify_signature(message, &group.key, &sig));
+    }
+
+    /// Tests that a rogue key chosen after seeing the victim's key cannot control the aggregate.
+    #[test]
+    fn test_musig_rogue_key_resistance() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let message = b"approve transfer #7";
+
+        let victim = ZKP::generate_random_below(&zkp.q);
+        let (victim_y, _) = zkp.compute_pair(&victim);
+
+        // The attacker publishes y_a = alpha^z / y_victim, so the naive product of
+        // the two keys would be alpha^z, a key the attacker fully controls.
+        let z = ZKP::generate_random_below(&zkp.q);
+        let (target, _) = zkp.compute_pair(&z);
+        let rogue = &target * zkp.inverse(&victim_y) % &zkp.p;
+        assert_eq!(&victim_y * &rogue % &zkp.p, target);
+
+        // With MuSig coefficients the aggregate is not the attacker's key ...
+        let group = zkp.aggregate_keys(&[victim_y.clone(), rogue.clone()]);
+        assert_ne!(group.key, target);
+
+        // ... so a signature made with z alone does not verify for the group.
+        let forged = zkp.sign(message, &z);
+        assert!(!zkp.verify_signature(message, &group.key, &forged));
+
+        // A secret that is not in the group cannot produce a partial signature.
+        let r = zkp.musig_nonce().r;
+        assert!(zkp.musig_partial_sign(&group, zkp.musig_nonce(), &r, &z, message).is_none());
+    }
+
+    /// Tests Shamir sharing of the secret and reconstruction from any threshold subset.
+    #[test]
+    fn test_shamir_secret_sharing() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+
+        let x = ZKP::generate_random_below(&zkp.q);
+        let shares = zkp.split_secret(&x, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+
+        // Any three shares recover the secret, two do not.
+        assert_eq!(zkp.reconstruct_secret(&shares[..3]), Some(x.clone()));
+        assert_eq!(zkp.reconstruct_secret(&[shares[4].clone(), shares[1].clone(), shares[3].clone()]), Some(x.clone()));
+        assert_ne!(zkp.reconstruct_secret(&shares[..2]), Some(x.clone()));
+
+        // Repeated or zero indices are refused instead of giving a wrong secret.
+        assert!(zkp.reconstruct_secret(&[shares[0].clone(), shares[0].clone(), shares[1].clone()]).is_none());
+        let zero = SecretShare { index: 0, value: x.clone() };
+        assert!(zkp.reconstruct_secret(&[zero, shares[1].clone(), shares[2].clone()]).is_none());
+
+        assert!(zkp.split_secret(&x, 0, 5).is_none());
+        assert!(zkp.split_secret(&x, 6, 5).is_none());
+    }
+
+    /// Tests a 2-of-3 threshold login whose combined response passes `verify`.
+    #[test]
+    fn test_threshold_response() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+
+        // Register with the public keys of x, then split x across three devices.
+        let x = ZKP::generate_random_below(&zkp.q);
+        let (y1, y2) = zkp.compute_pair(&x);
+        let shares = zkp.split_secret(&x, 2, 3).unwrap();
+
+        // Devices 1 and 3 cooperate: each commits to its own nonce.
+        let cooperating = [shares[0].clone(), shares[2].clone()];
+        let indices: Vec<u32> = cooperating.iter().map(|share| share.index).collect();
+        let nonces: Vec<BigUint> = cooperating.iter().map(|_| ZKP::generate_random_below(&zkp.q)).collect();
+        let commitments: Vec<(BigUint, BigUint)> = nonces.iter().map(|k| zkp.compute_pair(k)).collect();
+        let (r1, r2) = zkp.combine_commitments(&commitments);
+
+        // The verifier's challenge is answered with the combined partial responses.
+        let c = ZKP::generate_random_below(&zkp.q);
+        let partials: Vec<BigUint> = cooperating
+            .iter()
+            .zip(&nonces)
+            .map(|(share, k)| zkp.partial_response(k, &c, share, &indices))
+            .collect();
+        let s = zkp.combine_responses(&partials);
+        assert!(zkp.verify(&r1, &r2, &y1, &y2, &c, &s));
+
+        // A single device cannot answer on its own.
+        let s = zkp.partial_response(&nonces[0], &c, &cooperating[0], &indices[..1]);
+        assert!(!zkp.verify(&commitments[0].0, &commitments[0].1, &y1, &y2, &c, &s));
+    }
+
+    /// Tests ElGamal encryption, exponential ElGamal and proofs of correct decryption.
+    #[test]
+    fn test_elgamal_with_proof_of_decryption() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let (x, y) = zkp.elgamal_keygen();
+
+        // Plain ElGamal on a group element.
+        let m = zkp.alpha.modpow(&ZKP::generate_random_below(&zkp.q), &zkp.p);
+        let ciphertext = zkp.encrypt(&y, &m);
+        assert_eq!(zkp.decrypt(&x, &ciphertext), m);
+
+        // Exponential ElGamal adds plaintexts homomorphically.
+        let sum = zkp.add_ciphertexts(&zkp.encrypt_exponential(&y, 20), &zkp.encrypt_exponential(&y, 22));
+        assert_eq!(zkp.decrypt_exponential(&x, &sum, 100), Some(42));
+        assert_eq!(zkp.decrypt_exponential(&x, &sum, 41), None);
+
+        // The decryptor proves it used the secret behind y.
+        let (decrypted, proof) = zkp.prove_decryption(&x, &ciphertext);
+        assert!(zkp.verify_decryption(&y, &ciphertext, &decrypted, &proof));
+
+        // Claiming a different plaintext or using another key is rejected.
+        let wrong = &decrypted * &zkp.alpha % &zkp.p;
+        assert!(!zkp.verify_decryption(&y, &ciphertext, &wrong, &proof));
+        let (other_x, _) = zkp.elgamal_keygen();
+        let (_, other_proof) = zkp.prove_decryption(&other_x, &ciphertext);
+        assert!(!zkp.verify_decryption(&y, &ciphertext, &decrypted, &other_proof));
+
+        // With c1 = 0 the second equation holds for any plaintext, since both
+        // sides are 0. Such a proof for a made-up plaintext is rejected.
+        let zero = BigUint::from(0u32);
+        let forged = Ciphertext { c1: zero.clone(), c2: ciphertext.c2.clone() };
+        let claimed = zkp.alpha.modpow(&ZKP::generate_random_below(&zkp.q), &zkp.p);
+        let shared = &forged.c2 * zkp.inverse(&claimed) % &zkp.p;
+        let k = ZKP::generate_random_below(&zkp.q);
+        let r1 = zkp.alpha.modpow(&k, &zkp.p);
+        let c = zkp.with_beta(&zero).dleq_challenge(&y, &shared, &r1, &zero, &[]);
+        let proof = DleqProof { r1, r2: zero.clone(), s: zkp.response(&k, &c, &x) };
+        assert!(zkp.with_beta(&zero).verify(&proof.r1, &proof.r2, &y, &shared, &c, &proof.s));
+        assert!(!zkp.verify_decryption(&y, &forged, &claimed, &proof));
+    }
+
+    /// Tests the DLEQ-based VRF: outputs are deterministic per key and message and verifiable.
+    #[test]
+    fn test_vrf() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let (x, y) = zkp.elgamal_keygen();
+
+        let proof = zkp.vrf_prove(&x, b"epoch 17");
+        assert!(zkp.vrf_verify(&y, b"epoch 17", &proof));
+        assert!(!zkp.vrf_verify(&y, b"epoch 18", &proof));
+
+        // The output is unique: a second evaluation yields the same hash.
+        let again = zkp.vrf_prove(&x, b"epoch 17");
+        assert_eq!(zkp.vrf_proof_to_hash(&proof), zkp.vrf_proof_to_hash(&again));
+        assert_ne!(zkp.vrf_proof_to_hash(&proof), zkp.vrf_proof_to_hash(&zkp.vrf_prove(&x, b"epoch 18")));
+
+        // Another key cannot claim this output.
+        let (_, other_y) = zkp.elgamal_keygen();
+        assert!(!zkp.vrf_verify(&other_y, b"epoch 17", &proof));
+    }
+
+    /// Tests a verifiable OPRF round trip from blinding to redemption.
+    #[test]
+    fn test_verifiable_oprf() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let (k, y) = zkp.elgamal_keygen();
+        let input = b"token nonce 1";
+
+        // Client blinds, server evaluates with a proof, client unblinds and finalizes.
+        let (r, blinded) = zkp.oprf_blind(input);
+        let (evaluated, proof) = zkp.oprf_evaluate(&k, &blinded).unwrap();
+        let unblinded = zkp.oprf_unblind(&y, &blinded, &evaluated, &proof, &r).unwrap();
+        let output = zkp.oprf_finalize(input, &unblinded);
+
+        // The server recomputes the same output without ever seeing the blinded element.
+        assert_eq!(output, zkp.oprf_full_evaluate(&k, input));
+
+        // An evaluation under a key other than the published one is rejected.
+        let (other_k, _) = zkp.elgamal_keygen();
+        let (evaluated, proof) = zkp.oprf_evaluate(&other_k, &blinded).unwrap();
+        assert!(zkp.oprf_unblind(&y, &blinded, &evaluated, &proof, &r).is_none());
+
+        // Elements outside the subgroup are not evaluated.
+        assert!(zkp.oprf_evaluate(&k, &(&zkp.p - 1u32)).is_none());
+    }
+
+    /// Tests that a blind Schnorr signature verifies and is unlinkable to the session.
+    #[test]
+    fn test_blind_schnorr_signature() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let (x, y) = zkp.elgamal_keygen();
+        let message = b"credential serial 0451";
+
+        // Signer commits to a nonce, the client blinds it along with the challenge.
+        let k = ZKP::generate_random_below(&zkp.q);
+        let signer_r = zkp.alpha.modpow(&k, &zkp.p);
+        let state = zkp.blind_challenge(&y, &signer_r, message);
+        let e = state.e.clone();
+
+        // The signer answers and the client unblinds the result.
+        let s = zkp.blind_sign(&k, &e, &x);
+        let sig = zkp.unblind_signature(state, &y, &s).unwrap();
+        assert!(zkp.verify_signature(message, &y, &sig));
+
+        // Neither the nonce nor the response seen by the signer appear in the signature.
+        assert_ne!(sig.r, signer_r);
+        assert_ne!(sig.s, s);
+
+        // A wrong answer from the signer is detected before unblinding.
+        let state = zkp.blind_challenge(&y, &signer_r, message);
+        let wrong = zkp.blind_sign(&k, &state.e, &(&x + 1u32));
+        assert!(zkp.unblind_signature(state, &y, &wrong).is_none());
+    }
+
+    /// Tests linkable ring signatures, including double-signing detection.
+    #[test]
+    fn test_linkable_ring_signature() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+
+        // A ring of four registered keys.
+        let secrets: Vec<BigUint> = (0..4).map(|_| ZKP::generate_random_below(&zkp.q)).collect();
+        let ring: Vec<BigUint> = secrets.iter().map(|x| zkp.compute_pair(x).0).collect();
+
+        let vote = zkp.ring_sign(&ring, &secrets[2], b"election 3", b"yes").unwrap();
+        assert!(zkp.verify_ring_signature(&ring, b"election 3", b"yes", &vote));
+        assert!(!zkp.verify_ring_signature(&ring, b"election 3", b"no", &vote));
+        assert!(!zkp.verify_ring_signature(&ring[1..], b"election 3", b"yes", &vote));
+
+        // Signing twice in the same scope is detected, even over a grown ring.
+        let mut grown = ring.clone();
+        grown.push(zkp.compute_pair(&ZKP::generate_random_below(&zkp.q)).0);
+        let second = zkp.ring_sign(&grown, &secrets[2], b"election 3", b"no").unwrap();
+        assert!(zkp.verify_ring_signature(&grown, b"election 3", b"no", &second));
+        assert!(vote.is_linked_to(&second));
+
+        // Other members and other scopes are not linked.
+        let other = zkp.ring_sign(&ring, &secrets[0], b"election 3", b"yes").unwrap();
+        assert!(!vote.is_linked_to(&other));
+        let next = zkp.ring_sign(&ring, &secrets[2], b"election 4", b"yes").unwrap();
+        assert!(!vote.is_linked_to(&next));
+
+        // Outsiders cannot sign for the ring.
+        let outsider = ZKP::generate_random_below(&zkp.q);
+        assert!(zkp.ring_sign(&ring, &outsider, b"election 3", b"yes").is_none());
+    }
+
+    /// Tests Merkle inclusion paths for every leaf of odd and even sized trees.
+    #[test]
+    fn test_merkle_tree_paths() {
+        let hasher = Sha256Hasher;
+        for size in 1..=9usize {
+            let leaves: Vec<Vec<u8>> = (0..size).map(|i| format!("leaf {}", i).into_bytes()).collect();
+            let tree = MerkleTree::new(hasher, &leaves);
+            let root = tree.root();
+
+            for (i, leaf) in leaves.iter().enumerate() {
+                let path = tree.path(i).unwrap();
+                assert!(MerkleTree::verify_path(&hasher, &root, leaf, &path));
+                assert!(!MerkleTree::verify_path(&hasher, &root, b"intruder", &path));
+            }
+            assert!(tree.path(size).is_none());
+        }
+
+        // Appending and replacing leaves matches a tree built from scratch.
+        let mut tree = MerkleTree::new(hasher, &[b"a".to_vec(), b"b".to_vec()]);
+        assert_eq!(tree.push(b"c"), 2);
+        assert!(tree.set(0, b"z"));
+        let rebuilt = MerkleTree::new(hasher, &[b"z".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+        assert_eq!(tree.root(), rebuilt.root());
+
+        // The same holds at every size, with every path, growing from empty.
+        let mut tree = MerkleTree::<Sha256Hasher>::default();
+        let mut leaves = Vec::new();
+        for i in 0..17usize {
+            leaves.push(format!("leaf {}", i).into_bytes());
+            assert_eq!(tree.push(&leaves[i]), i);
+            assert!(tree.set(i / 2, b"replaced"));
+            leaves[i / 2] = b"replaced".to_vec();
+            let rebuilt = MerkleTree::new(hasher, &leaves);
+            assert_eq!(tree.root(), rebuilt.root());
+            for j in 0..=i {
+                assert_eq!(tree.path(j), rebuilt.path(j));
+            }
+        }
+    }
+
+    /// Tests a Chaum–Pedersen membership proof bound to a tree of registrations.
+    #[test]
+    fn test_membership_proof() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+
+        // Build the tree of registered (y1, y2) pairs.
+        let secrets: Vec<BigUint> = (0..5).map(|_| ZKP::generate_random_below(&zkp.q)).collect();
+        let leaves: Vec<Vec<u8>> = secrets
+            .iter()
+            .map(|x| {
+                let (y1, y2) = zkp.compute_pair(x);
+                ZKP::registration_leaf(&y1, &y2)
+            })
+            .collect();
+        let tree = MerkleTree::new(Sha256Hasher, &leaves);
+        let root = tree.root();
+
+        let proof = zkp.prove_membership(&secrets[3], tree.path(3).unwrap(), &root, b"nonce 1");
+        assert!(zkp.verify_membership(&Sha256Hasher, &root, b"nonce 1", &proof));
+
+        // The proof is bound to its context and root.
+        assert!(!zkp.verify_membership(&Sha256Hasher, &root, b"nonce 2", &proof));
+        let other_root = MerkleTree::new(Sha256Hasher, &leaves[..4]).root();
+        assert!(!zkp.verify_membership(&Sha256Hasher, &other_root, b"nonce 1", &proof));
+
+        // A secret whose registration is not in the tree cannot prove membership.
+        let outsider = ZKP::generate_random_below(&zkp.q);
+        let proof = zkp.prove_membership(&outsider, tree.path(3).unwrap(), &root, b"nonce 1");
+        assert!(!zkp.verify_membership(&Sha256Hasher, &root, b"nonce 1", &proof));
+    }
+
+    /// Tests a verifiable shuffle of ElGamal ciphertexts.
+    #[test]
+    fn test_verifiable_shuffle() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let (x, y) = zkp.elgamal_keygen();
+        let rounds = MIN_SHUFFLE_ROUNDS;
+
+        let votes = [3u64, 1, 4, 1, 5];
+        let input: Vec<Ciphertext> = votes.iter().map(|&v| zkp.encrypt_exponential(&y, v)).collect();
+        let (output, permutation, randomness) = zkp.shuffle(&y, &input);
+
+        let proof = zkp.prove_shuffle(&y, &input, &output, &permutation, &randomness, rounds);
+        assert!(zkp.verify_shuffle(&y, &input, &output, &proof, rounds));
+        assert!(!zkp.verify_shuffle(&y, &input, &output, &proof, rounds + 1));
+
+        // The output decrypts to the same multiset of plaintexts.
+        let mut shuffled: Vec<u64> = output.iter().map(|c| zkp.decrypt_exponential(&x, c, 10).unwrap()).collect();
+        shuffled.sort();
+        assert_eq!(shuffled, vec![1, 1, 3, 4, 5]);
+
+        // Replacing a ciphertext in the output breaks the proof.
+        let mut tampered = output.clone();
+        tampered[0] = zkp.encrypt_exponential(&y, 9);
+        assert!(!zkp.verify_shuffle(&y, &input, &tampered, &proof, rounds));
+
+        // A valid proof with too few rounds is refused.
+        let short = zkp.prove_shuffle(&y, &input, &output, &permutation, &randomness, 40);
+        assert!(!zkp.verify_shuffle(&y, &input, &output, &short, 40));
+
+        // So is an honest shuffle of a ciphertext outside the subgroup.
+        let mut outside = input.clone();
+        outside[0].c1 = BigUint::from(0u32);
+        let (output, permutation, randomness) = zkp.shuffle(&y, &outside);
+        let proof = zkp.prove_shuffle(&y, &outside, &output, &permutation, &randomness, rounds);
+        assert!(!zkp.verify_shuffle(&y, &outside, &output, &proof, rounds));
+    }
+
+    /// Tests ballot validity proofs, homomorphic tallying and threshold decryption.
+    #[test]
+    fn test_homomorphic_tally_with_threshold_decryption() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+
+        // A trusted dealer shares the election key 2-of-3 among trustees.
+        let (x, y) = zkp.elgamal_keygen();
+        let shares = zkp.split_secret(&x, 2, 3).unwrap();
+        let verification_keys: Vec<BigUint> = shares.iter().map(|s| zkp.compute_pair(&s.value).0).collect();
+
+        let votes = [true, false, true, true, false];
+        let voters: Vec<Vec<u8>> = (0..votes.len()).map(|i| format!("voter {}", i).into_bytes()).collect();
+        let ballots: Vec<Ballot> =
+            votes.iter().zip(&voters).map(|(&v, voter)| zkp.cast_ballot(&y, b"election", voter, v)).collect();
+        assert!(ballots.iter().zip(&voters).all(|(b, voter)| zkp.verify_ballot(&y, b"election", voter, b)));
+
+        // A ballot is bound to its voter and election.
+        assert!(!zkp.verify_ballot(&y, b"election", &voters[1], &ballots[0]));
+        assert!(!zkp.verify_ballot(&y, b"other election", &voters[0], &ballots[0]));
+
+        // A ballot for "2" cannot carry a valid proof.
+        let mut stuffed = ballots[0].clone();
+        stuffed.ciphertext.c2 = &stuffed.ciphertext.c2 * &zkp.alpha % &zkp.p;
+        assert!(!zkp.verify_ballot(&y, b"election", &voters[0], &stuffed));
+
+        // With c1 = 0 and c2 = alpha * y^w both branch relations can be satisfied
+        // without a vote; such a ballot satisfies the proof equation but is refused.
+        let w = ZKP::generate_random_below(&zkp.q);
+        let forged = Ciphertext { c1: BigUint::from(0u32), c2: &zkp.alpha * y.modpow(&w, &zkp.p) % &zkp.p };
+        let group = zkp.with_beta(&y);
+        let targets = zkp.ballot_targets(&forged);
+        let (e0, s0) = (ZKP::generate_random_below(&zkp.q), ZKP::generate_random_below(&zkp.q));
+        let k = ZKP::generate_random_below(&zkp.q);
+        let commitments = [
+            zkp.ballot_commitment(&group, &forged.c1, &targets[0], &e0, &s0),
+            (BigUint::from(0u32), y.modpow(&k, &zkp.p)),
+        ];
+        let context = ballot_context(b"election", &voters[0]);
+        let e1 = (zkp.ballot_challenge(&y, &context, &forged, &commitments) + &zkp.q - &e0) % &zkp.q;
+        let s1 = zkp.response(&k, &e1, &w);
+        assert_eq!(zkp.ballot_commitment(&group, &forged.c1, &targets[1], &e1, &s1), commitments[1]);
+        let forged = Ballot { ciphertext: forged, proof: BallotProof { e0, e1, s0, s1 } };
+        assert!(!zkp.verify_ballot(&y, b"election", &voters[0], &forged));
+
+        // Trustees 1 and 3 decrypt the tally with verifiable shares.
+        let tally = zkp.tally_ballots(&ballots);
+        let decryption: Vec<DecryptionShare> = [0, 2].iter().map(|&i| zkp.decryption_share(&shares[i], &tally)).collect();
+        assert!(zkp.verify_decryption_share(&verification_keys[0], &tally, &decryption[0]));
+        assert!(zkp.verify_decryption_share(&verification_keys[2], &tally, &decryption[1]));
+        assert!(!zkp.verify_decryption_share(&verification_keys[1], &tally, &decryption[1]));
+        assert_eq!(zkp.combine_decryption_shares(&tally, &decryption, votes.len() as u64), Some(3));
+
+        // A single trustee cannot decrypt.
+        assert_ne!(zkp.combine_decryption_shares(&tally, &decryption[..1], votes.len() as u64), Some(3));
+
+        // Duplicate or zero indices are refused instead of combined.
+        let duplicated = [decryption[0].clone(), decryption[0].clone()];
+        assert_eq!(zkp.combine_decryption_shares(&tally, &duplicated, votes.len() as u64), None);
+        let mut zero = decryption.clone();
+        zero[0].index = 0;
+        assert_eq!(zkp.combine_decryption_shares(&tally, &zero, votes.len() as u64), None);
+
+        // A trustee sending the negated share p - d with a proof whose challenge
+        // is even satisfies the proof equation, but the share is refused.
+        let negated = &zkp.p - &decryption[0].d;
+        let group = zkp.with_beta(&tally.c1);
+        let forged = loop {
+            let k = ZKP::generate_random_below(&zkp.q);
+            let (r1, r2) = group.compute_pair(&k);
+            let c = group.dleq_challenge(&verification_keys[0], &negated, &r1, &r2, &[]);
+            if !c.bit(0) {
+                let proof = DleqProof { r1, r2, s: zkp.response(&k, &c, &shares[0].value) };
+                break DecryptionShare { index: 1, d: negated.clone(), proof };
+            }
+        };
+        assert!(group.verify_dleq(&verification_keys[0], &forged.d, &forged.proof));
+        assert!(!zkp.verify_decryption_share(&verification_keys[0], &tally, &forged));
+    }
+
+    /// Simulates a distributed key generation among five servers, two of them corrupt.
+    #[test]
+    fn test_distributed_key_generation() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        const SERVERS: usize = 5;
+        const CORRUPT: usize = 2;
+        let threshold = CORRUPT + 1;
+
+        // Every server deals; shares[d][j] is what dealer d+1 privately sends to server j+1.
+        let (dealings, mut shares): (Vec<DkgDealing>, Vec<Vec<SecretShare>>) = (1..=SERVERS as u32)
+            .map(|dealer| zkp.dkg_deal(dealer, threshold, SERVERS).unwrap())
+            .unzip();
+
+        // Corrupt dealer 2 sends server 4 a bad share, but answers the complaint honestly.
+        let honest_answer = shares[1][3].clone();
+        shares[1][3].value = (&shares[1][3].value + 1u32) % &zkp.q;
+        // Corrupt dealer 5 sends servers 1 and 3 bad shares and answers with another bad share.
+        shares[4][0].value = (&shares[4][0].value + 1u32) % &zkp.q;
+        shares[4][2].value = (&shares[4][2].value + 1u32) % &zkp.q;
+
+        // Servers check their shares and complain about the bad ones.
+        let mut complaints = Vec::new();
+        for (dealing, dealt) in dealings.iter().zip(&shares) {
+            for (j, share) in dealt.iter().enumerate() {
+                complaints.extend(zkp.dkg_check_share(dealing, j as u32 + 1, share));
+            }
+        }
+        assert_eq!(complaints.len(), 3);
+
+        let answers = vec![(2, honest_answer.clone()), (5, shares[4][0].clone())];
+        let qualified = zkp.dkg_qualified(&dealings, &complaints, &answers, threshold);
+        assert_eq!(qualified, vec![1, 2, 3, 4]);
+
+        // Each server sums the (possibly re-issued) shares of the qualified dealers.
+        shares[1][3] = honest_answer;
+        let final_shares: Vec<SecretShare> = (1..=SERVERS as u32)
+            .map(|j| {
+                let received: Vec<SecretShare> = qualified
+                    .iter()
+                    .map(|&dealer| shares[dealer as usize - 1][j as usize - 1].clone())
+                    .collect();
+                zkp.dkg_combine_shares(j, &received)
+            })
+            .collect();
+
+        // Every share matches its public verification key.
+        let public_key = zkp.dkg_public_key(&dealings, &qualified);
+        for share in &final_shares {
+            let key = zkp.dkg_verification_key(&dealings, &qualified, share.index);
+            assert_eq!(zkp.compute_pair(&share.value).0, key);
+        }
+
+        // Any threshold of servers holds the joint secret; the corrupt ones alone do not.
+        let x = zkp.reconstruct_secret(&final_shares[2..]).unwrap();
+        assert_eq!(zkp.compute_pair(&x).0, public_key);
+        let colluding = [final_shares[1].clone(), final_shares[4].clone()];
+        assert_ne!(zkp.compute_pair(&zkp.reconstruct_secret(&colluding).unwrap()).0, public_key);
+
+        // The joint key decrypts a tally without any single party knowing it.
+        let ballots: Vec<Ballot> = [true, true, false]
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| zkp.cast_ballot(&public_key, b"election", &i.to_be_bytes(), v))
+            .collect();
+        let tally = zkp.tally_ballots(&ballots);
+        let decryption: Vec<DecryptionShare> =
+            final_shares[..threshold].iter().map(|share| zkp.decryption_share(share, &tally)).collect();
+        assert_eq!(zkp.combine_decryption_shares(&tally, &decryption, 3), Some(2));
+    }
+
+    /// Tests that client and server derive the same session key from a login.
+    #[test]
+    fn test_session_key_from_login() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+
+        // Registration and the client's commitment.
+        let x = ZKP::generate_random_below(&zkp.q);
+        let (y1, y2) = zkp.compute_pair(&x);
+        let k = ZKP::generate_random_below(&zkp.q);
+        let (r1, r2) = zkp.compute_pair(&k);
+
+        // The server answers with a challenge and its ephemeral share.
+        let b = ZKP::generate_random_below(&zkp.q);
+        let (server_share, _) = zkp.compute_pair(&b);
+        let c = ZKP::generate_random_below(&zkp.q);
+        let s = zkp.response(&k, &c, &x);
+        assert!(zkp.verify(&r1, &r2, &y1, &y2, &c, &s));
+
+        let transcript = LoginTranscript { user: "alice", auth_id: "a1", r1: &r1, r2: &r2, server_share: &server_share, c: &c, s: &s };
+        let client_key = zkp.derive_session_key(&server_share.modpow(&k, &zkp.p), &transcript);
+        let server_key = zkp.derive_session_key(&r1.modpow(&b, &zkp.p), &transcript);
+        assert_eq!(client_key, server_key);
+
+        // The key is bound to the transcript.
+        let other = LoginTranscript { auth_id: "a2", ..transcript };
+        assert_ne!(zkp.derive_session_key(&r1.modpow(&b, &zkp.p), &other), server_key);
+
+        // Requests can be authenticated with the key.
+        let tag = session_mac(&client_key, b"IssueToken");
+        assert!(verify_session_mac(&server_key, b"IssueToken", &tag));
+        assert!(!verify_session_mac(&server_key, b"RedeemToken", &tag));
     }
 }
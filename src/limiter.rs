@@ -0,0 +1,214 @@
+// Rate limits and lockout for login attempts.
+//
+// Challenges are limited per user and per peer address within a fixed window.
+// Failed verifications of a user's proof make that user back off for an
+// exponentially growing time, ending in a lockout. The lockout also applies
+// to an attacker who only wants to keep the real user out, so its duration
+// should stay short.
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct LimitConfig {
+    //challenges a single user can request per window, `ZKP_USER_RATE_LIMIT`
+    pub user_requests: u32,
+    //challenges a single peer address can request per window, `ZKP_PEER_RATE_LIMIT`
+    pub peer_requests: u32,
+    pub window: Duration,
+    //failed verifications that are not penalized
+    pub free_failures: u32,
+    //wait after the first penalized failure, doubling with every further one,
+    //`ZKP_BACKOFF_BASE_SECS`
+    pub backoff_base: Duration,
+    //failed verifications after which the user is locked out, `ZKP_LOCKOUT_THRESHOLD`
+    pub lockout_threshold: u32,
+    //how long a lockout lasts, and the cap on any backoff, `ZKP_LOCKOUT_SECS`
+    pub lockout: Duration,
+}
+
+impl Default for LimitConfig {
+    fn default() -> Self {
+        LimitConfig {
+            user_requests: 10,
+            peer_requests: 100,
+            window: Duration::from_secs(60),
+            free_failures: 3,
+            backoff_base: Duration::from_secs(1),
+            lockout_threshold: 10,
+            lockout: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Window {
+    started: Instant,
+    count: u32,
+}
+
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    last: Instant,
+}
+
+#[derive(Debug, Default)]
+struct LimiterState {
+    users: HashMap<String, Window>,
+    peers: HashMap<IpAddr, Window>,
+    failures: HashMap<String, Failures>,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: LimitConfig,
+    state: Mutex<LimiterState>,
+}
+
+impl Window {
+    // Starts a new window when the current one is over, then returns how long
+    // to wait if `limit` requests were already made in it.
+    fn wait(&mut self, limit: u32, length: Duration, now: Instant) -> Option<Duration> {
+        if now.duration_since(self.started) >= length {
+            *self = Window { started: now, count: 0 };
+        }
+        (self.count >= limit).then(|| length - now.duration_since(self.started))
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: LimitConfig) -> Self {
+        RateLimiter {
+            config,
+            state: Mutex::default(),
+        }
+    }
+
+    // How long a user with `count` failures must wait after the last one.
+    fn penalty(&self, count: u32) -> Duration {
+        if count >= self.config.lockout_threshold {
+            return self.config.lockout;
+        }
+        if count < self.config.free_failures {
+            return Duration::ZERO;
+        }
+        let doublings = (count - self.config.free_failures).min(31);
+        self.config
+            .backoff_base
+            .saturating_mul(1 << doublings)
+            .min(self.config.lockout)
+    }
+
+    // Returns how long `user` still has to wait if they are backing off or
+    // locked out. Answers are checked too, so challenges fetched before the
+    // lockout cannot be used to keep guessing.
+    pub fn check_penalty(&self, user: &str) -> Result<(), Duration> {
+        self.penalty_wait(&self.state.lock().unwrap(), user, Instant::now())
+    }
+
+    fn penalty_wait(&self, state: &LimiterState, user: &str, now: Instant) -> Result<(), Duration> {
+        if let Some(failures) = state.failures.get(user) {
+            let wait = self.penalty(failures.count);
+            let elapsed = now.duration_since(failures.last);
+            if elapsed < wait {
+                return Err(wait - elapsed);
+            }
+        }
+        Ok(())
+    }
+
+    // Admits a challenge request for `user` from `peer`.
+    //
+    // Returns how long the client has to wait if the user is backing off or
+    // locked out, or a rate limit is exhausted.
+    pub fn check(&self, user: &str, peer: Option<IpAddr>) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        self.penalty_wait(&state, user, now)?;
+
+        // Only count the request once both limits admit it.
+        let length = self.config.window;
+        let state = &mut *state;
+        let user_window = state.users.entry(user.to_string()).or_insert(Window { started: now, count: 0 });
+        if let Some(wait) = user_window.wait(self.config.user_requests, length, now) {
+            return Err(wait);
+        }
+        if let Some(peer) = peer {
+            let peer_window = state.peers.entry(peer).or_insert(Window { started: now, count: 0 });
+            if let Some(wait) = peer_window.wait(self.config.peer_requests, length, now) {
+                return Err(wait);
+            }
+            peer_window.count += 1;
+        }
+        user_window.count += 1;
+        Ok(())
+    }
+
+    // Records a failed verification of `user`'s proof.
+    pub fn record_failure(&self, user: &str) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let failures = state.failures.entry(user.to_string()).or_insert(Failures { count: 0, last: now });
+        failures.count += 1;
+        failures.last = now;
+    }
+
+    // Records a successful verification, clearing the user's failures.
+    pub fn record_success(&self, user: &str) {
+        self.state.lock().unwrap().failures.remove(user);
+    }
+
+    // Forgets windows that are over and failures older than a lockout.
+    pub fn sweep(&self) {
+        let now = Instant::now();
+        let window = self.config.window;
+        let mut state = self.state.lock().unwrap();
+        state.users.retain(|_, entry| now.duration_since(entry.started) < window);
+        state.peers.retain(|_, entry| now.duration_since(entry.started) < window);
+        let lockout = self.config.lockout;
+        state.failures.retain(|_, failures| now.duration_since(failures.last) < lockout);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Requests are limited per user and per peer, failures lead to an
+    /// exponential backoff and then a lockout, and a success clears them.
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(LimitConfig {
+            user_requests: 2,
+            peer_requests: 3,
+            ..Default::default()
+        });
+        let peer: IpAddr = "10.0.0.1".parse().unwrap();
+
+        // Each user gets two requests; the peer as a whole gets three.
+        assert!(limiter.check("alice", Some(peer)).is_ok());
+        assert!(limiter.check("alice", Some(peer)).is_ok());
+        let wait = limiter.check("alice", Some(peer)).unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_secs(60));
+        assert!(limiter.check("bob", Some(peer)).is_ok());
+        assert!(limiter.check("carol", Some(peer)).is_err());
+        assert!(limiter.check("carol", None).is_ok());
+
+        // The first failures are free, then the wait doubles up to the lockout.
+        assert_eq!(limiter.penalty(2), Duration::ZERO);
+        assert_eq!(limiter.penalty(3), Duration::from_secs(1));
+        assert_eq!(limiter.penalty(5), Duration::from_secs(4));
+        assert_eq!(limiter.penalty(10), Duration::from_secs(15 * 60));
+
+        for _ in 0..3 {
+            limiter.record_failure("dave");
+        }
+        assert!(limiter.check("dave", None).unwrap_err() <= Duration::from_secs(1));
+
+        // A success clears the failures.
+        limiter.record_success("dave");
+        assert!(limiter.check("dave", None).is_ok());
+    }
+}
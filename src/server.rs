@@ -1,36 +1,479 @@
-
+// Helpers return `tonic::Status` errors just like the handlers do.
+#![allow(clippy::result_large_err)]
 // Import necessary modules from Tonic and other dependencies.
 use tonic::{transport::Server, Code, Request, Response,Status};
 use num_bigint::BigUint;
-use std::sync::Mutex;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::{Duration, Instant, SystemTime};
 use std::collections::HashMap;
-use rust_zero_knowledge::ZKP;
+use rust_zero_knowledge::interceptor::SESSION_MAC_METADATA_KEY;
+use rust_zero_knowledge::session::{ClientMetadata, Session, SessionStore};
+use rust_zero_knowledge::token::{unix_time, TokenClaims, TokenIssuer, DEFAULT_LEEWAY};
+use rust_zero_knowledge::{request_mac_data, session_mac, verify_session_mac, DleqProof, LoginTranscript, MerkleTree, ZKP};
+use prost::Message;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use limiter::{LimitConfig, RateLimiter};
+use store::{MemoryStore, SqliteStore, StoreError, StoreResult, UserRecord, UserStore, DEFAULT_GROUP_ID};
+
+mod limiter;
+mod store;
 
 pub mod zkp_auth {
     include!("./zkp_auth.rs");
 }
 // Import types and traits related to the Auth service from the generated code.
-use zkp_auth::{auth_server::{Auth,AuthServer},RegisterRequest,RegisterResponse,AuthenticationChallengeRequest,AuthenticationChallengeResponse,AuthenticationAnswerRequest,AuthenticationAnswerResponse};
+use zkp_auth::{auth_server::{Auth,AuthServer},RegisterRequest,RegisterResponse,AuthenticationChallengeRequest,AuthenticationChallengeResponse,AuthenticationAnswerRequest,AuthenticationAnswerResponse,IssueTokenRequest,IssueTokenResponse,RedeemTokenRequest,RedeemTokenResponse,GetAccessTokenKeyRequest,GetAccessTokenKeyResponse,BlindCredentialCommitmentRequest,BlindCredentialCommitmentResponse,IssueBlindCredentialRequest,IssueBlindCredentialResponse,GetRingRequest,GetRingResponse,GetMembershipPathRequest,GetMembershipPathResponse,RotateCredentialRequest,RotateCredentialResponse,ValidateSessionRequest,ValidateSessionResponse,RefreshSessionRequest,RefreshSessionResponse,LogoutRequest,LogoutResponse,GetTokenKeysRequest,GetTokenKeysResponse,TokenKey};
 // Define a struct to implement the Auth service.
-#[derive(Debug,Default)]
+#[derive(Debug)]
 pub struct AuthImpl{
-    pub user_info: Mutex<HashMap<String, UserInfo>>,
-    //another hashmap to store relationship between user and auth_id
-    pub auth_id_to_user:Mutex<HashMap<String,String>>,
+    //all mutable server state lives behind this single lock, so handlers never
+    //hold two locks at once and cannot deadlock on lock order
+    pub state: Mutex<AuthState>,
+    //registered users, kept in memory or on disk depending on the config
+    pub users: Box<dyn UserStore>,
+    //how long a challenge or a blind credential issuance can be answered
+    pub challenge_ttl: Duration,
+    //how many blind credential issuances may be open at once, across all users
+    pub max_open_credentials: usize,
+    //sessions issued by a successful verification
+    pub sessions: Arc<SessionStore>,
+    //limits login attempts per user and per peer
+    pub limiter: RateLimiter,
+    //secret from which the stand-in salts of unknown users are derived
+    pub server_secret: [u8; 32],
+    //public keys that unknown users are checked against, so that logging in
+    //as an unknown user costs the same as a wrong answer for a known one
+    pub unknown_user_keys: (BigUint, BigUint),
+    //lifetime of signed login tokens; no tokens are issued when unset
+    pub token_ttl: Option<Duration>,
+    //how long a token signing key is used before it is rotated
+    pub token_key_lifetime: Duration,
+    //OPRF key used to issue anonymous access tokens, derived from the config
+    //so that tokens outlive a restart
+    pub token_key: BigUint,
+    //key used to blindly sign anonymous credentials
+    pub credential_key: BigUint,
+}
+impl Default for AuthImpl{
+    fn default() -> Self {
+        AuthImpl::new(Box::<MemoryStore>::default(), &ServerConfig::default()).expect("an empty store cannot fail")
+    }
+}
+impl AuthImpl{
+    // Creates the service on top of `users`, rebuilding the membership tree from
+    // the users that are already registered.
+    pub fn new(users: Box<dyn UserStore>, config: &ServerConfig) -> StoreResult<Self> {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+
+        let mut state = AuthState::default();
+        if config.token_ttl.is_some() {
+            state.token_issuer = Some(TokenIssuer::new(&zkp));
+        }
+        for user in users.all()? {
+            let index = state.membership.tree.push(&ZKP::registration_leaf(&user.y1, &user.y2));
+            state.membership.positions.insert(user.user_name, index);
+        }
+
+        Ok(AuthImpl{
+            state: Mutex::new(state),
+            users,
+            challenge_ttl: config.challenge_ttl,
+            max_open_credentials: config.max_open_credentials,
+            sessions: Arc::new(SessionStore::new(config.session_ttl)),
+            limiter: RateLimiter::new(config.limits.clone()),
+            server_secret: match &config.server_secret {
+                Some(secret) => Sha256::digest(secret.as_bytes()).into(),
+                None => Sha256::digest(ZKP::generate_random_string(32)).into(),
+            },
+            unknown_user_keys: zkp.compute_pair(&ZKP::generate_random_below(&zkp.q)),
+            token_ttl: config.token_ttl,
+            token_key_lifetime: config.token_key_lifetime,
+            token_key: match &config.access_token_secret {
+                Some(secret) => {
+                    let seed = BigUint::from_bytes_be(&Sha256::digest(secret.as_bytes()));
+                    zkp.hash_to_challenge(b"zkp-oprf-key", &[&seed])
+                }
+                None => ZKP::generate_random_below(&zkp.q),
+            },
+            credential_key: ZKP::generate_random_below(&zkp.q),
+        })
+    }
+    // Locks the server state. The guard must not be held across an `.await`
+    // or while doing expensive proof verification.
+    fn state(&self) -> MutexGuard<'_, AuthState> {
+        self.state.lock().unwrap()
+    }
+    // Removes the challenge `auth_id`, so it can only be answered once, and
+    // checks that it has not expired. A challenge made for another purpose is
+    // left in place and reported as missing.
+    fn take_challenge(&self, auth_id: &str, purpose: ChallengePurpose) -> Result<PendingChallenge, Status> {
+        let challenge = {
+            let mut state = self.state();
+            match state.challenges.get(auth_id) {
+                Some(challenge) if challenge.purpose == purpose => state.challenges.remove(auth_id),
+                _ => None,
+            }
+        };
+        let Some(challenge) = challenge else {
+            return Err(Status::new(
+                Code::NotFound,
+                format!("AuthId: {} not found in database", auth_id),
+            ));
+        };
+        if challenge.created_at.elapsed() >= self.challenge_ttl {
+            return Err(Status::new(
+                Code::DeadlineExceeded,
+                format!("AuthId: {} expired", auth_id),
+            ));
+        }
+        Ok(challenge)
+    }
+    // Creates a challenge for `request` that can only be answered for `purpose`.
+    fn create_challenge(
+        &self,
+        peer: Option<IpAddr>,
+        request: AuthenticationChallengeRequest,
+        purpose: ChallengePurpose,
+    ) -> Result<AuthenticationChallengeResponse, Status> {
+        let user_name = request.user;
+
+        // Throttle guessing before doing any work for the request.
+        self.limiter.check(&user_name, peer).map_err(retry_later)?;
+
+        // Load the registration; it must belong to the group this server uses.
+        // Unknown users get a challenge all the same, which no answer can solve.
+        let record = self.user_record(&user_name)?;
+        if record.group_id != DEFAULT_GROUP_ID {
+            return Err(Status::new(
+                Code::FailedPrecondition,
+                format!("User: {} is registered in group {}", user_name, record.group_id),
+            ));
+        }
+
+        // Retrieve constants for ZKP (Zero-Knowledge Proof).
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+
+        // Generate a random challenge number 'c' and a unique authentication ID 'auth_id'.
+        let c = ZKP::generate_random_below(&zkp.q);
+        let auth_id = ZKP::generate_random_string(12);
+
+        // Generate the ephemeral key exchange share for the session key.
+        let b = ZKP::generate_random_below(&zkp.q);
+        let (server_share, _) = zkp.compute_pair(&b);
+
+        // Locking the server state to ensure thread-safe access.
+        let mut state = self.state();
+
+        // Store challenge number and received values (r1, r2) under the auth_id.
+        let challenge = PendingChallenge {
+            user_name,
+            purpose,
+            r1: BigUint::from_bytes_be(&request.r1),
+            r2: BigUint::from_bytes_be(&request.r2),
+            c: c.clone(),
+            b,
+            created_at: Instant::now(),
+        };
+        state.challenges.insert(auth_id.clone(), challenge);
+
+        // Return authentication challenge response with auth_id and challenge number.
+        Ok(AuthenticationChallengeResponse {
+            auth_id,
+            c: c.to_bytes_be(),
+            server_share: server_share.to_bytes_be(),
+            salt: record.salt,
+        })
+    }
+    // Loads the registration of `user_name`. Unknown users get a stand-in record
+    // instead, whose salt is derived from the server secret so that it is as
+    // stable as a real one, and whose keys no answer will match. Callers treat
+    // both the same way, so responses do not reveal who is registered.
+    fn user_record(&self, user_name: &str) -> Result<UserRecord, Status> {
+        if let Some(record) = self.users.get(user_name).map_err(store_error)? {
+            return Ok(record);
+        }
+
+        // Shaped like the salts the client generates: 16 alphanumeric characters.
+        const ALPHANUMERIC: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+        let digest = session_mac(&self.server_secret, format!("salt:{}", user_name).as_bytes());
+        let salt = digest[..16]
+            .iter()
+            .map(|byte| ALPHANUMERIC[*byte as usize % ALPHANUMERIC.len()])
+            .collect();
+
+        let (y1, y2) = self.unknown_user_keys.clone();
+        Ok(UserRecord {
+            user_name: user_name.to_string(),
+            y1,
+            y2,
+            salt,
+            group_id: DEFAULT_GROUP_ID.to_string(),
+        })
+    }
+    // Returns the live session `session_id`, or Unauthenticated.
+    fn session(&self, session_id: &str) -> Result<Session, Status> {
+        self.sessions
+            .validate(session_id)
+            .ok_or_else(|| Status::new(Code::Unauthenticated, "Session is not valid"))
+    }
+    // Returns the live session `session_id` once the request carries a valid MAC
+    // under its session key, so that a leaked session id alone is not enough.
+    fn signed_session<T: Message>(&self, request: &Request<T>, method: &str, session_id: &str) -> Result<Session, Status> {
+        let session = self.session(session_id)?;
+        let tag = request.metadata().get_bin(SESSION_MAC_METADATA_KEY).and_then(|tag| tag.to_bytes().ok());
+        let data = request_mac_data(method, &request.get_ref().encode_to_vec());
+        match tag {
+            Some(tag) if verify_session_mac(&session.session_key, &data, &tag) => Ok(session),
+            _ => Err(Status::new(Code::Unauthenticated, "Request MAC is not valid")),
+        }
+    }
+    // Drops the challenges and blind credential nonces that are older than the
+    // TTL and returns how many were removed.
+    pub fn sweep_challenges(&self) -> usize {
+        let mut state = self.state();
+        let before = state.challenges.len() + state.credential_nonces.len();
+        state.challenges.retain(|_, challenge| challenge.created_at.elapsed() < self.challenge_ttl);
+        state.credential_nonces.retain(|_, pending| pending.created_at.elapsed() < self.challenge_ttl);
+        before - state.challenges.len() - state.credential_nonces.len()
+    }
+    // Replaces the token signing key once it is older than its lifetime. The old
+    // public key stays published until the last token it signed has expired,
+    // including the leeway verifiers allow past expiry.
+    pub fn rotate_token_key(&self) {
+        let Some(token_ttl) = self.token_ttl else { return };
+        let issuer = self.state().token_issuer.clone();
+        let Some(issuer) = issuer else { return };
+        let now = SystemTime::now();
+
+        let mut updated = issuer.clone();
+        updated.prune(now);
+        if issuer.created_at() + self.token_key_lifetime <= now {
+            let (alpha, beta, p, q) = ZKP::get_constants();
+            let zkp = ZKP { alpha, beta, p, q };
+            updated.rotate(&zkp, token_ttl + DEFAULT_LEEWAY);
+            println!("Rotated token key, now signing with key_id: {:?}", updated.current_key().key_id);
+        }
+        self.state().token_issuer = Some(updated);
+    }
+    // Starts a task that sweeps expired challenges, sessions and rate limit
+    // entries and rotates the token key, once per the shorter of the two TTLs. The task ends once the
+    // service itself is dropped.
+    pub fn spawn_sweeper(self: &Arc<Self>) -> tokio::task::JoinHandle<()> {
+        let auth: Weak<Self> = Arc::downgrade(self);
+        let period = self.challenge_ttl.min(self.sessions.ttl());
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                let Some(auth) = auth.upgrade() else { break };
+                let removed = auth.sweep_challenges();
+                if removed > 0 {
+                    println!("Swept {} expired challenges", removed);
+                }
+                let removed = auth.sessions.sweep();
+                if removed > 0 {
+                    println!("Swept {} expired sessions", removed);
+                }
+                auth.rotate_token_key();
+                auth.limiter.sweep();
+            }
+        })
+    }
+}
+#[derive(Debug,Default)]
+pub struct AuthState{
+    //open login attempts keyed by auth_id
+    pub challenges: HashMap<String,PendingChallenge>,
+    //open blind credential issuances keyed by credential_id
+    pub credential_nonces: HashMap<String,PendingCredential>,
+    //Merkle tree over the registered (y1, y2) pairs
+    pub membership: MembershipTree,
+    //signs login tokens when they are enabled
+    pub token_issuer: Option<TokenIssuer>,
+}
+// Server settings read from the environment.
+#[derive(Debug)]
+pub struct ServerConfig{
+    //address to listen on, `ZKP_ADDR`
+    pub addr: String,
+    //SQLite database holding the registered users, `ZKP_DB_PATH`;
+    //users are only kept in memory when unset
+    pub db_path: Option<String>,
+    //how long an auth_id or a credential_id can be answered,
+    //`ZKP_CHALLENGE_TTL_SECS`
+    pub challenge_ttl: Duration,
+    //open blind credential issuances across all users, `ZKP_MAX_OPEN_CREDENTIALS`;
+    //concurrent sessions under one key open the signer to the ROS attack, so
+    //the default of 1 serializes issuance
+    pub max_open_credentials: usize,
+    //how long a session lives without a refresh, `ZKP_SESSION_TTL_SECS`
+    pub session_ttl: Duration,
+    //lifetime of the signed token returned at login, `ZKP_TOKEN_TTL_SECS`;
+    //no tokens are issued when unset
+    pub token_ttl: Option<Duration>,
+    //how long a token signing key is used before it is replaced,
+    //`ZKP_TOKEN_KEY_LIFETIME_SECS`
+    pub token_key_lifetime: Duration,
+    //rate limits, backoff and lockout of login attempts
+    pub limits: LimitConfig,
+    //secret that keeps the answers for unknown users stable across restarts,
+    //`ZKP_SERVER_SECRET`; a random one is used when unset
+    pub server_secret: Option<String>,
+    //secret the OPRF key of access tokens is derived from,
+    //`ZKP_ACCESS_TOKEN_SECRET`; a random key is used when unset, so
+    //outstanding tokens stop working on restart
+    pub access_token_secret: Option<String>,
+}
+impl Default for ServerConfig{
+    fn default() -> Self {
+        ServerConfig{
+            addr: "127.0.0.1:50051".to_string(),
+            db_path: None,
+            challenge_ttl: Duration::from_secs(60),
+            max_open_credentials: 1,
+            session_ttl: Duration::from_secs(60 * 60),
+            token_ttl: None,
+            token_key_lifetime: Duration::from_secs(24 * 60 * 60),
+            limits: LimitConfig::default(),
+            server_secret: None,
+            access_token_secret: None,
+        }
+    }
+}
+impl ServerConfig{
+    pub fn from_env() -> Self {
+        let default = ServerConfig::default();
+        let config = ServerConfig{
+            addr: std::env::var("ZKP_ADDR").unwrap_or(default.addr),
+            db_path: std::env::var("ZKP_DB_PATH").ok(),
+            challenge_ttl: env_secs("ZKP_CHALLENGE_TTL_SECS").unwrap_or(default.challenge_ttl),
+            max_open_credentials: env_number("ZKP_MAX_OPEN_CREDENTIALS").unwrap_or(default.max_open_credentials),
+            session_ttl: env_secs("ZKP_SESSION_TTL_SECS").unwrap_or(default.session_ttl),
+            token_ttl: env_secs("ZKP_TOKEN_TTL_SECS"),
+            token_key_lifetime: env_secs("ZKP_TOKEN_KEY_LIFETIME_SECS").unwrap_or(default.token_key_lifetime),
+            limits: LimitConfig{
+                user_requests: env_number("ZKP_USER_RATE_LIMIT").unwrap_or(default.limits.user_requests),
+                peer_requests: env_number("ZKP_PEER_RATE_LIMIT").unwrap_or(default.limits.peer_requests),
+                backoff_base: env_secs("ZKP_BACKOFF_BASE_SECS").unwrap_or(default.limits.backoff_base),
+                lockout_threshold: env_number("ZKP_LOCKOUT_THRESHOLD").unwrap_or(default.limits.lockout_threshold),
+                lockout: env_secs("ZKP_LOCKOUT_SECS").unwrap_or(default.limits.lockout),
+                ..default.limits
+            },
+            server_secret: std::env::var("ZKP_SERVER_SECRET").ok(),
+            access_token_secret: std::env::var("ZKP_ACCESS_TOKEN_SECRET").ok(),
+        };
+        // Unknown users must get the same stand-in salt after a restart as the
+        // persisted users get their real one, or a restart tells them apart.
+        if config.db_path.is_some() && config.server_secret.is_none() {
+            panic!("ZKP_DB_PATH needs ZKP_SERVER_SECRET to keep the salts of unknown users stable");
+        }
+        // Spent tokens live in the user store; in memory they would be forgotten
+        // on restart while the key, and so the tokens, stay valid.
+        if config.access_token_secret.is_some() && config.db_path.is_none() {
+            panic!("ZKP_ACCESS_TOKEN_SECRET needs ZKP_DB_PATH to remember spent tokens");
+        }
+        config
+    }
+    // Opens the user store selected by the config.
+    pub fn user_store(&self) -> StoreResult<Box<dyn UserStore>> {
+        match &self.db_path {
+            Some(path) => Ok(Box::new(SqliteStore::open(path)?)),
+            None => Ok(Box::<MemoryStore>::default()),
+        }
+    }
+}
+// Reads a number of seconds from the environment variable `name`.
+fn env_secs(name: &str) -> Option<Duration> {
+    env_number(name).map(Duration::from_secs)
+}
+// Reads a number from the environment variable `name`.
+fn env_number<T: std::str::FromStr>(name: &str) -> Option<T> {
+    let value = std::env::var(name).ok()?;
+    Some(value.parse().unwrap_or_else(|_| panic!("{} must be a number", name)))
+}
+// Tells the client to slow down and when to try again, both in the message and
+// in the `retry-after` metadata entry (whole seconds, rounded up).
+fn retry_later(wait: Duration) -> Status {
+    let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    let mut status = Status::new(
+        Code::ResourceExhausted,
+        format!("Too many login attempts, retry in {} seconds", secs),
+    );
+    status.metadata_mut().insert("retry-after", secs.into());
+    status
+}
+// Auth method recorded in signed login tokens.
+const AUTH_METHOD: &str = "chaum-pedersen";
+// Collects what is known about the client sending `request`.
+fn client_metadata<T>(request: &Request<T>) -> ClientMetadata {
+    ClientMetadata {
+        peer: request.remote_addr().map(|addr| addr.to_string()),
+        user_agent: request
+            .metadata()
+            .get("user-agent")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+    }
+}
+fn store_error(error: StoreError) -> Status {
+    Status::new(Code::Internal, error.to_string())
 }
 #[derive(Debug,Default)]
-pub struct UserInfo{
-    //registration
+pub struct MembershipTree{
+    pub tree: MerkleTree,
+    //leaf index of each registered user
+    pub positions: HashMap<String, usize>,
+}
+impl MembershipTree{
+    // Adds the user's (y1, y2) leaf, replacing the leaf of earlier keys.
+    pub fn update(&mut self, record: &UserRecord) {
+        let leaf = ZKP::registration_leaf(&record.y1, &record.y2);
+        match self.positions.get(&record.user_name) {
+            Some(&index) => {
+                self.tree.set(index, &leaf);
+            }
+            None => {
+                let index = self.tree.push(&leaf);
+                self.positions.insert(record.user_name.clone(), index);
+            }
+        }
+    }
+}
+// What a challenge may be answered for. Login challenges cannot authorize a
+// credential rotation, so an answer captured at login cannot be used to take
+// over the account.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum ChallengePurpose{
+    Login,
+    Rotation,
+}
+// A login attempt between the challenge and its answer. Each attempt has its
+// own record, so concurrent logins of the same user do not interfere.
+#[derive(Debug,Clone)]
+pub struct PendingChallenge{
+    pub user_name: String,
+    pub purpose: ChallengePurpose,
+    //commitments alpha^k and beta^k sent by the client
+    pub r1: BigUint,
+    pub r2: BigUint,
+    //challenge sent back to the client
+    pub c: BigUint,
+    //ephemeral key exchange secret b, the server share is alpha^b
+    pub b: BigUint,
+    pub created_at: Instant,
+}
+// A blind credential issuance between the nonce commitment and the signature.
+#[derive(Debug,Clone)]
+pub struct PendingCredential{
+    pub session_id: String,
     pub user_name: String,
-    pub y1 : BigUint,
-    pub y2: BigUint,
-    //authorization
-    pub r1:  BigUint,
-    pub r2:  BigUint,
-    //verification
-    pub c:  BigUint,
-    pub s:  BigUint,
-    pub session_id:  BigUint,
+    //signer's nonce, its commitment alpha^k was sent to the client
+    pub k: BigUint,
+    pub created_at: Instant,
 }
 // Implement the Auth trait for the AuthImpl struct.
 // This trait contains the service methods as defined in the .proto file.
@@ -45,13 +488,22 @@ impl Auth for AuthImpl{
         let user_name = request.user;
 
 
-        let mut new_user_info = UserInfo::default();
-        new_user_info.user_name = user_name.clone();
-        new_user_info.y1 = BigUint::from_bytes_be(&request.y1);
-        new_user_info.y2 = BigUint::from_bytes_be(&request.y2);
-
-        let mut user_info_map = self.user_info.lock().unwrap();
-        user_info_map.insert(user_name, new_user_info);
+        let record = UserRecord {
+            user_name: user_name.clone(),
+            y1: BigUint::from_bytes_be(&request.y1),
+            y2: BigUint::from_bytes_be(&request.y2),
+            salt: request.salt,
+            group_id: DEFAULT_GROUP_ID.to_string(),
+        };
+        // Never overwrite an existing user; their keys can only change through RotateCredential.
+        // The store and the membership tree change under the state lock, so
+        // concurrent writes for one user leave the same keys in both.
+        let mut state = self.state();
+        if !self.users.insert(&record).map_err(store_error)? {
+            return Err(Status::new(Code::AlreadyExists, format!("User: {} is already registered", user_name)));
+        }
+        state.membership.update(&record);
+        drop(state);
 
         Ok(Response::new(RegisterResponse{}))
     }
@@ -60,38 +512,16 @@ impl Auth for AuthImpl{
 // Asynchronous function to create an authentication challenge.
     async fn create_authentication_challenge(&self, request: Request<AuthenticationChallengeRequest>) -> Result<Response<AuthenticationChallengeResponse>, Status> {
         println!("Processing Authentication");
-        let request = request.into_inner();
-
-        let user_name = request.user;
-
-        // Locking the user_info map to ensure thread-safe access.
-        let mut user_info_map = self.user_info.lock().unwrap();
-
-        // Check if user exists in user_info map and process authentication challenge.
-        if let Some(user_info) = user_info_map.get_mut(&user_name) {
-
-            // Retrieve constants for ZKP (Zero-Knowledge Proof).
-            let (_, _, _, q) = ZKP::get_constants();
-
-            // Generate a random challenge number 'c' and a unique authentication ID 'auth_id'.
-            let c = ZKP::generate_random_below(&q);
-            let auth_id = ZKP::generate_random_string(12);
-
-            // Store challenge number and received values (r1, r2) in user's info.
-            user_info.c = c.clone();
-            user_info.r1 = BigUint::from_bytes_be(&request.r1);
-            user_info.r2 = BigUint::from_bytes_be(&request.r2);
-
-            // Map auth_id to user name in auth_id_to_user map.
-            let auth_id_to_user_map = &mut self.auth_id_to_user.lock().unwrap();
-            auth_id_to_user_map.insert(auth_id.clone(), user_name);
+        let peer = request.remote_addr().map(|addr| addr.ip());
+        self.create_challenge(peer, request.into_inner(), ChallengePurpose::Login).map(Response::new)
+    }
 
-            // Return authentication challenge response with auth_id and challenge number.
-            Ok(Response::new(AuthenticationChallengeResponse { auth_id, c: c.to_bytes_be() }))
-        } else {
-            // Return error if user is not found in the database.
-            Err(Status::new(Code::NotFound, format!("User: {} not found in database", user_name)))
-        }
+    // Implement the `create_rotation_challenge` method.
+    // This method creates a challenge that only `rotate_credential` accepts.
+    async fn create_rotation_challenge(&self, request: Request<AuthenticationChallengeRequest>) -> Result<Response<AuthenticationChallengeResponse>, Status> {
+        println!("Processing Rotation Challenge");
+        let peer = request.remote_addr().map(|addr| addr.ip());
+        self.create_challenge(peer, request.into_inner(), ChallengePurpose::Rotation).map(Response::new)
     }
 
     // Implement the `verify_authentication` method.
@@ -101,70 +531,1088 @@ async fn verify_authentication(
     &self,
     request: Request<AuthenticationAnswerRequest>,
 ) -> Result<Response<AuthenticationAnswerResponse>, Status> {
+    let client = client_metadata(&request);
     let request = request.into_inner();
 
     let auth_id = request.auth_id;
     println!("Processing Challenge Solution auth_id: {:?}", auth_id);
 
-    let auth_id_to_user_hashmap = &mut self.auth_id_to_user.lock().unwrap();
+    // Take the challenge out under the lock, then verify without holding it.
+    // Removing it makes every auth_id single-use, whether the answer is right or not.
+    let challenge = self.take_challenge(&auth_id, ChallengePurpose::Login)?;
+    let user_name = challenge.user_name;
+    // A user who is backing off or locked out cannot answer challenges fetched earlier.
+    self.limiter.check_penalty(&user_name).map_err(retry_later)?;
+    let record = self.user_record(&user_name)?;
+    let s = BigUint::from_bytes_be(&request.s);
+
+    let (alpha, beta, p, q) = ZKP::get_constants();
+    let zkp = ZKP { alpha, beta, p, q };
+
+    let verification = zkp.verify(&challenge.r1, &challenge.r2, &record.y1, &record.y2, &challenge.c, &s);
 
-    if let Some(user_name) = auth_id_to_user_hashmap.get(&auth_id) {
-        let user_info_hashmap = &mut self.user_info.lock().unwrap();
-        let user_info = user_info_hashmap
-            .get_mut(user_name)
-            .expect("AuthId not found on hashmap");
+    if verification {
+        self.limiter.record_success(&user_name);
+
+        // Derive the session key from r1^b, bound to the whole login transcript.
+        let (server_share, _) = zkp.compute_pair(&challenge.b);
+        let transcript = LoginTranscript {
+            user: &user_name,
+            auth_id: &auth_id,
+            r1: &challenge.r1,
+            r2: &challenge.r2,
+            server_share: &server_share,
+            c: &challenge.c,
+            s: &s,
+        };
+        let session_key = zkp.derive_session_key(&challenge.r1.modpow(&challenge.b, &zkp.p), &transcript);
+        let session = self.sessions.create(&user_name, session_key, client);
+
+        // Sign a self-contained token for services that verify logins offline.
+        let issuer = self.state().token_issuer.clone();
+        let token = match (self.token_ttl, issuer) {
+            (Some(ttl), Some(issuer)) => {
+                let now = SystemTime::now();
+                let claims = TokenClaims {
+                    user: user_name.clone(),
+                    issued_at: unix_time(now),
+                    expires_at: unix_time(now + ttl),
+                    auth_method: AUTH_METHOD.to_string(),
+                    group_id: record.group_id,
+                };
+                issuer.issue(&zkp, claims).to_bytes()
+            }
+            _ => Vec::new(),
+        };
+
+        println!("Correct Challenge Solution username: {:?}", user_name);
+
+        Ok(Response::new(AuthenticationAnswerResponse {
+            session_id: session.id,
+            expires_at: unix_time(session.expires_at),
+            token,
+        }))
+    } else {
+        println!(" Wrong Challenge Solution username: {:?}", user_name);
+        self.limiter.record_failure(&user_name);
 
-        let s = BigUint::from_bytes_be(&request.s);
-        user_info.s = s;
+        Err(Status::new(
+            Code::PermissionDenied,
+            format!("AuthId: {} bad solution to the challenge", auth_id),
+        ))
+    }
+}
+    // Implement the `issue_token` method.
+    // This method evaluates the OPRF on a blinded token for an authenticated session.
+    async fn issue_token(&self, request: Request<IssueTokenRequest>) -> Result<Response<IssueTokenResponse>, Status> {
+        self.signed_session(&request, "IssueToken", &request.get_ref().session_id)?;
+        let request = request.into_inner();
+        println!("Issuing token for session_id: {:?}", request.session_id);
 
         let (alpha, beta, p, q) = ZKP::get_constants();
         let zkp = ZKP { alpha, beta, p, q };
 
-        let verification = zkp.verify(
-            &user_info.r1,
-            &user_info.r2,
-            &user_info.y1,
-            &user_info.y2,
-            &user_info.c,
-            &user_info.s,
-        );
+        let blinded = BigUint::from_bytes_be(&request.blinded_element);
+        let (evaluated, DleqProof { r1, r2, s }) = zkp
+            .oprf_evaluate(&self.token_key, &blinded)
+            .ok_or_else(|| Status::new(Code::InvalidArgument, "Blinded element is not a group element"))?;
+
+        Ok(Response::new(IssueTokenResponse {
+            evaluated_element: evaluated.to_bytes_be(),
+            proof_r1: r1.to_bytes_be(),
+            proof_r2: r2.to_bytes_be(),
+            proof_s: s.to_bytes_be(),
+        }))
+    }
+
+    // Implement the `redeem_token` method.
+    // This method checks a token against the OPRF key and records it as spent.
+    async fn redeem_token(&self, request: Request<RedeemTokenRequest>) -> Result<Response<RedeemTokenResponse>, Status> {
+        let request = request.into_inner();
+
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+
+        // Compare in constant time, so the output cannot be guessed byte by byte.
+        let expected = zkp.oprf_full_evaluate(&self.token_key, &request.token_input);
+        if !bool::from(expected[..].ct_eq(&request.token_output[..])) {
+            return Err(Status::new(Code::PermissionDenied, "Token is not valid"));
+        }
+
+        // Record the input as spent; a second redemption of the same input fails.
+        if !self.users.spend_token(&request.token_input).map_err(store_error)? {
+            return Err(Status::new(Code::AlreadyExists, "Token was already redeemed"));
+        }
+        println!("Redeemed access token");
+
+        Ok(Response::new(RedeemTokenResponse {}))
+    }
+
+    // Implement the `get_access_token_key` method.
+    // This method publishes the OPRF public key that every token is issued under.
+    async fn get_access_token_key(&self, _request: Request<GetAccessTokenKeyRequest>) -> Result<Response<GetAccessTokenKeyResponse>, Status> {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let (public_key, _) = zkp.compute_pair(&self.token_key);
+
+        Ok(Response::new(GetAccessTokenKeyResponse { public_key: public_key.to_bytes_be() }))
+    }
+
+    // Implement the `create_blind_credential_commitment` method.
+    // This method opens a blind credential issuance and returns the signer's nonce commitment.
+    async fn create_blind_credential_commitment(&self, request: Request<BlindCredentialCommitmentRequest>) -> Result<Response<BlindCredentialCommitmentResponse>, Status> {
+        let session = self.signed_session(&request, "CreateBlindCredentialCommitment", &request.get_ref().session_id)?;
+        let request = request.into_inner();
+
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+
+        let k = ZKP::generate_random_below(&zkp.q);
+        let (r, _) = zkp.compute_pair(&k);
+        let (public_key, _) = zkp.compute_pair(&self.credential_key);
+        let credential_id = ZKP::generate_random_string(12);
+
+        // Each open issuance holds a nonce until it is answered or expires. The
+        // cap is global: accounts are free, so a per-user cap would still let
+        // one attacker hold the many concurrent sessions the ROS attack needs.
+        {
+            let mut state = self.state();
+            let open = state
+                .credential_nonces
+                .values()
+                .filter(|pending| pending.created_at.elapsed() < self.challenge_ttl)
+                .count();
+            if open >= self.max_open_credentials {
+                return Err(Status::new(Code::ResourceExhausted, "Too many open blind credentials"));
+            }
+            state.credential_nonces.insert(
+                credential_id.clone(),
+                PendingCredential { session_id: request.session_id, user_name: session.user_name, k, created_at: Instant::now() },
+            );
+        }
+        println!("Opening blind credential credential_id: {:?}", credential_id);
+
+        Ok(Response::new(BlindCredentialCommitmentResponse {
+            credential_id,
+            r: r.to_bytes_be(),
+            public_key: public_key.to_bytes_be(),
+        }))
+    }
+
+    // Implement the `issue_blind_credential` method.
+    // This method answers the blinded challenge, consuming the issuance's nonce.
+    async fn issue_blind_credential(&self, request: Request<IssueBlindCredentialRequest>) -> Result<Response<IssueBlindCredentialResponse>, Status> {
+        self.signed_session(&request, "IssueBlindCredential", &request.get_ref().session_id)?;
+        let request = request.into_inner();
+
+        // The nonce is removed up front so it can never answer two challenges.
+        let pending = self
+            .state()
+            .credential_nonces
+            .remove(&request.credential_id)
+            .ok_or_else(|| Status::new(Code::NotFound, format!("CredentialId: {} not found in database", request.credential_id)))?;
+        if pending.session_id != request.session_id {
+            return Err(Status::new(Code::PermissionDenied, "Credential was opened by another session"));
+        }
+        if pending.created_at.elapsed() >= self.challenge_ttl {
+            return Err(Status::new(Code::DeadlineExceeded, format!("CredentialId: {} expired", request.credential_id)));
+        }
+
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+
+        let e = BigUint::from_bytes_be(&request.e);
+        let s = zkp.blind_sign(&pending.k, &e, &self.credential_key);
+        println!("Issued blind credential credential_id: {:?}", request.credential_id);
+
+        Ok(Response::new(IssueBlindCredentialResponse { s: s.to_bytes_be() }))
+    }
+
+    // Implement the `get_ring` method.
+    // This method returns the y1 values of all registered users, ordered by user name.
+    async fn get_ring(&self, _request: Request<GetRingRequest>) -> Result<Response<GetRingResponse>, Status> {
+        let users = self.users.all().map_err(store_error)?;
+        let public_keys = users.iter().map(|user| user.y1.to_bytes_be()).collect();
+
+        Ok(Response::new(GetRingResponse { public_keys }))
+    }
+
+    // Implement the `get_membership_path` method.
+    // This method returns the membership tree root and the user's inclusion path.
+    async fn get_membership_path(&self, request: Request<GetMembershipPathRequest>) -> Result<Response<GetMembershipPathResponse>, Status> {
+        // Only the user's own session may ask, or the answer would tell
+        // registered users from unknown ones.
+        let session = self.signed_session(&request, "GetMembershipPath", &request.get_ref().session_id)?;
+        let user_name = request.into_inner().user;
+        if session.user_name != user_name {
+            return Err(Status::new(Code::Unauthenticated, "Session is not valid"));
+        }
+        let state = self.state();
+        let membership = &state.membership;
+
+        let path = membership
+            .positions
+            .get(&user_name)
+            .and_then(|&index| membership.tree.path(index))
+            .ok_or_else(|| Status::new(Code::NotFound, format!("User: {} not found in database", user_name)))?;
+
+        Ok(Response::new(GetMembershipPathResponse {
+            root: membership.tree.root(),
+            leaf_index: path.index as u64,
+            leaf_count: path.leaf_count as u64,
+            siblings: path.siblings,
+        }))
+    }
+
+    // Implement the `validate_session` method.
+    // This method reports the user and lifetime of a live session.
+    async fn validate_session(&self, request: Request<ValidateSessionRequest>) -> Result<Response<ValidateSessionResponse>, Status> {
+        let session = self.session(&request.into_inner().session_id)?;
+
+        Ok(Response::new(ValidateSessionResponse {
+            user: session.user_name,
+            created_at: unix_time(session.created_at),
+            expires_at: unix_time(session.expires_at),
+        }))
+    }
+
+    // Implement the `refresh_session` method.
+    // This method extends a live session by the session TTL.
+    async fn refresh_session(&self, request: Request<RefreshSessionRequest>) -> Result<Response<RefreshSessionResponse>, Status> {
+        self.signed_session(&request, "RefreshSession", &request.get_ref().session_id)?;
+        let session = self
+            .sessions
+            .refresh(&request.into_inner().session_id)
+            .ok_or_else(|| Status::new(Code::Unauthenticated, "Session is not valid"))?;
+
+        Ok(Response::new(RefreshSessionResponse { expires_at: unix_time(session.expires_at) }))
+    }
+
+    // Implement the `logout` method.
+    // This method ends a session.
+    async fn logout(&self, request: Request<LogoutRequest>) -> Result<Response<LogoutResponse>, Status> {
+        self.signed_session(&request, "Logout", &request.get_ref().session_id)?;
+        let session = self
+            .sessions
+            .remove(&request.into_inner().session_id)
+            .ok_or_else(|| Status::new(Code::Unauthenticated, "Session is not valid"))?;
+        println!("Logged out username: {:?}", session.user_name);
+
+        Ok(Response::new(LogoutResponse {}))
+    }
+
+    // Implement the `get_token_keys` method.
+    // This method publishes the keys that login tokens may be signed with.
+    async fn get_token_keys(&self, _request: Request<GetTokenKeysRequest>) -> Result<Response<GetTokenKeysResponse>, Status> {
+        let keys = match &self.state().token_issuer {
+            Some(issuer) => issuer.public_keys(),
+            None => Vec::new(),
+        };
+
+        Ok(Response::new(GetTokenKeysResponse {
+            keys: keys
+                .into_iter()
+                .map(|key| TokenKey { key_id: key.key_id, public_key: key.public_key.to_bytes_be() })
+                .collect(),
+        }))
+    }
+
+    // Implement the `rotate_credential` method.
+    // This method replaces a user's public keys once the old key is proven or a session of the user is shown.
+    async fn rotate_credential(&self, request: Request<RotateCredentialRequest>) -> Result<Response<RotateCredentialResponse>, Status> {
+        let session = match request.get_ref().session_id.as_str() {
+            "" => None,
+            session_id => Some(self.signed_session(&request, "RotateCredential", session_id)?),
+        };
+        let request = request.into_inner();
+        let user_name = request.user;
+        println!("Processing Credential Rotation username: {:?}", user_name);
 
-        if verification {
-            let session_id = ZKP::generate_random_string(12);
+        let record = self.user_record(&user_name)?;
 
-            println!("Correct Challenge Solution username: {:?}", user_name);
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let y1 = BigUint::from_bytes_be(&request.y1);
+        let y2 = BigUint::from_bytes_be(&request.y2);
+        if !zkp.in_subgroup(&y1) || !zkp.in_subgroup(&y2) {
+            return Err(Status::new(Code::InvalidArgument, "New keys are not group elements"));
+        }
 
-            Ok(Response::new(AuthenticationAnswerResponse { session_id }))
+        if let Some(session) = session {
+            // A live session of the same user authorizes the rotation.
+            if session.user_name != user_name {
+                return Err(Status::new(Code::Unauthenticated, "Session is not valid"));
+            }
         } else {
-            println!(" Wrong Challenge Solution username: {:?}", user_name);
+            // Otherwise the request must answer a challenge of this user under the old key.
+            let challenge = self.take_challenge(&request.auth_id, ChallengePurpose::Rotation)?;
+            if challenge.user_name != user_name {
+                return Err(Status::new(
+                    Code::PermissionDenied,
+                    format!("AuthId: {} was issued to another user", request.auth_id),
+                ));
+            }
+            self.limiter.check_penalty(&user_name).map_err(retry_later)?;
 
-            Err(Status::new(
-                Code::PermissionDenied,
-                format!("AuthId: {} bad solution to the challenge", auth_id),
-            ))
+            // The answer is to the challenge bound to the new keys.
+            let c = zkp.rotation_challenge(&challenge.c, &y1, &y2, &request.salt);
+            let s = BigUint::from_bytes_be(&request.s);
+            if !zkp.verify(&challenge.r1, &challenge.r2, &record.y1, &record.y2, &c, &s) {
+                self.limiter.record_failure(&user_name);
+                return Err(Status::new(
+                    Code::PermissionDenied,
+                    format!("AuthId: {} bad solution to the challenge", request.auth_id),
+                ));
+            }
+            self.limiter.record_success(&user_name);
         }
-    } else {
-        Err(Status::new(
-            Code::NotFound,
-            format!("AuthId: {} not found in database", auth_id),
-        ))
+
+        let record = UserRecord { y1, y2, salt: request.salt, ..record };
+        let mut state = self.state();
+        if !self.users.update(&record).map_err(store_error)? {
+            return Err(Status::new(Code::NotFound, format!("User: {} not found in database", user_name)));
+        }
+        state.membership.update(&record);
+        drop(state);
+
+        // Sessions opened under the old key end with it.
+        let revoked = self.sessions.remove_user(&user_name);
+        println!("Revoked {} sessions of username: {:?}", revoked, user_name);
+
+        Ok(Response::new(RotateCredentialResponse {}))
     }
 }
-}
 #[tokio::main]
 async fn main() {
-    let addr = "127.0.0.1:50051".to_string();
-    println!("Running the server in {}", addr);
+    let config = ServerConfig::from_env();
+    let users = config.user_store().expect("Couldn't open the user store");
+    let auth = Arc::new(AuthImpl::new(users, &config).expect("Couldn't load the registered users"));
+    // Remove challenges that were never answered.
+    auth.spawn_sweeper();
+    println!("Running the server in {}", config.addr);
     // Build and run the server.
     Server::builder()
         // Add the Auth service to the server.
-        .add_service(AuthServer::new(AuthImpl::default()))
+        .add_service(AuthServer::from_arc(auth))
         // Start serving requests on the specified address.
         // If the address is invalid, the program will panic with the specified error message.
-        .serve(addr.parse().expect("Couldn't convert address"))
+        .serve(config.addr.parse().expect("Couldn't convert address"))
         // Await the server to run until it's terminated.
         .await
         // Unwrap the Result, panicking if the server fails to start (e.g., if the port is already in use).
         .unwrap();
 }
 
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_zero_knowledge::{MerklePath, Sha256Hasher};
+    use tonic::metadata::MetadataValue;
+
+    // Registers `user` with a fresh secret and returns the secret.
+    async fn register(auth: &AuthImpl, zkp: &ZKP, user: &str) -> BigUint {
+        let x = ZKP::generate_random_below(&zkp.q);
+        let (y1, y2) = zkp.compute_pair(&x);
+        auth.register(Request::new(RegisterRequest {
+            user: user.to_string(),
+            y1: y1.to_bytes_be(),
+            y2: y2.to_bytes_be(),
+            salt: Vec::new(),
+        }))
+        .await
+        .unwrap();
+        x
+    }
+
+    // Asks for a challenge as `user` and returns the nonce with the challenge.
+    async fn challenge(auth: &AuthImpl, zkp: &ZKP, user: &str) -> (BigUint, AuthenticationChallengeResponse) {
+        let k = ZKP::generate_random_below(&zkp.q);
+        let (r1, r2) = zkp.compute_pair(&k);
+        let response = auth
+            .create_authentication_challenge(Request::new(AuthenticationChallengeRequest {
+                user: user.to_string(),
+                r1: r1.to_bytes_be(),
+                r2: r2.to_bytes_be(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        (k, response)
+    }
+
+    // Asks for a rotation challenge as `user` and returns the nonce with the challenge.
+    async fn rotation_challenge(auth: &AuthImpl, zkp: &ZKP, user: &str) -> (BigUint, AuthenticationChallengeResponse) {
+        let k = ZKP::generate_random_below(&zkp.q);
+        let (r1, r2) = zkp.compute_pair(&k);
+        let response = auth
+            .create_rotation_challenge(Request::new(AuthenticationChallengeRequest {
+                user: user.to_string(),
+                r1: r1.to_bytes_be(),
+                r2: r2.to_bytes_be(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        (k, response)
+    }
+
+    // Answers a rotation challenge for `request` with the response for secret `x`.
+    fn rotation_answer(
+        zkp: &ZKP,
+        x: &BigUint,
+        k: &BigUint,
+        challenge: &AuthenticationChallengeResponse,
+        request: RotateCredentialRequest,
+    ) -> RotateCredentialRequest {
+        let c = zkp.rotation_challenge(
+            &BigUint::from_bytes_be(&challenge.c),
+            &BigUint::from_bytes_be(&request.y1),
+            &BigUint::from_bytes_be(&request.y2),
+            &request.salt,
+        );
+        let s = zkp.response(k, &c, x);
+        RotateCredentialRequest { auth_id: challenge.auth_id.clone(), s: s.to_bytes_be(), ..request }
+    }
+
+    // Wraps `message` in a request carrying its MAC under the key of `session_id`.
+    fn signed<T: Message>(auth: &AuthImpl, method: &str, session_id: &str, message: T) -> Request<T> {
+        let key = auth.sessions.validate(session_id).map_or([0; 32], |session| session.session_key);
+        let tag = session_mac(&key, &request_mac_data(method, &message.encode_to_vec()));
+        let mut request = Request::new(message);
+        request.metadata_mut().insert_bin(SESSION_MAC_METADATA_KEY, MetadataValue::from_bytes(&tag));
+        request
+    }
+
+    // Answers a challenge with the response for secret `x`.
+    async fn answer(
+        auth: &AuthImpl,
+        zkp: &ZKP,
+        x: &BigUint,
+        k: &BigUint,
+        challenge: &AuthenticationChallengeResponse,
+    ) -> Result<Response<AuthenticationAnswerResponse>, Status> {
+        let s = zkp.response(k, &BigUint::from_bytes_be(&challenge.c), x);
+        auth.verify_authentication(Request::new(AuthenticationAnswerRequest {
+            auth_id: challenge.auth_id.clone(),
+            s: s.to_bytes_be(),
+        }))
+        .await
+    }
+
+    /// Runs hundreds of clients in parallel against a shared `AuthImpl`, each
+    /// registering and logging in while the others create and answer challenges.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_parallel_clients() {
+        let auth = Arc::new(AuthImpl::default());
+
+        let mut clients = Vec::new();
+        for i in 0..200 {
+            let auth = Arc::clone(&auth);
+            clients.push(tokio::spawn(async move {
+                let (alpha, beta, p, q) = ZKP::get_constants();
+                let zkp = ZKP { alpha, beta, p, q };
+                let user = format!("user{}", i);
+
+                // Register and log in; challenges and answers of all clients interleave.
+                let x = register(&auth, &zkp, &user).await;
+                let (k, response) = challenge(&auth, &zkp, &user).await;
+                answer(&auth, &zkp, &x, &k, &response).await.unwrap();
+            }));
+        }
+
+        // Every client must finish, and every login must have produced a session.
+        for client in clients {
+            client.await.unwrap();
+        }
+        assert_eq!(auth.users.all().unwrap().len(), 200);
+        assert_eq!(auth.sessions.len(), 200);
+    }
+
+    /// Two logins of the same user that overlap both succeed, each checked
+    /// against its own commitment.
+    #[tokio::test]
+    async fn test_overlapping_logins() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let auth = AuthImpl::default();
+        let x = register(&auth, &zkp, "alice").await;
+
+        // Open both challenges before answering either.
+        let (k1, first) = challenge(&auth, &zkp, "alice").await;
+        let (k2, second) = challenge(&auth, &zkp, "alice").await;
+
+        // Answer them in reverse order.
+        assert!(answer(&auth, &zkp, &x, &k2, &second).await.is_ok());
+        assert!(answer(&auth, &zkp, &x, &k1, &first).await.is_ok());
+    }
+
+    /// An answered auth_id cannot be answered again, so replaying an old `s`
+    /// is rejected whether the first answer was right or wrong.
+    #[tokio::test]
+    async fn test_challenge_replay_rejected() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let auth = AuthImpl::default();
+        let x = register(&auth, &zkp, "alice").await;
+
+        // Replay the answer of a successful login.
+        let (k, response) = challenge(&auth, &zkp, "alice").await;
+        assert!(answer(&auth, &zkp, &x, &k, &response).await.is_ok());
+        let replay = answer(&auth, &zkp, &x, &k, &response).await.unwrap_err();
+        assert_eq!(replay.code(), Code::NotFound);
+
+        // A wrong answer also consumes the challenge.
+        let (k, response) = challenge(&auth, &zkp, "alice").await;
+        let wrong = ZKP::generate_random_below(&zkp.q);
+        let failed = answer(&auth, &zkp, &wrong, &k, &response).await.unwrap_err();
+        assert_eq!(failed.code(), Code::PermissionDenied);
+        let retry = answer(&auth, &zkp, &x, &k, &response).await.unwrap_err();
+        assert_eq!(retry.code(), Code::NotFound);
+    }
+
+    /// Challenges older than the TTL are refused and removed by the sweeper.
+    #[tokio::test]
+    async fn test_challenge_expiry() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let config = ServerConfig { challenge_ttl: Duration::from_millis(50), ..Default::default() };
+        let auth = Arc::new(AuthImpl::new(Box::<MemoryStore>::default(), &config).unwrap());
+        let x = register(&auth, &zkp, "alice").await;
+
+        // An answer after the TTL is too late.
+        let (k, response) = challenge(&auth, &zkp, "alice").await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let late = answer(&auth, &zkp, &x, &k, &response).await.unwrap_err();
+        assert_eq!(late.code(), Code::DeadlineExceeded);
+
+        // Unanswered challenges are swept in the background.
+        challenge(&auth, &zkp, "alice").await;
+        challenge(&auth, &zkp, "alice").await;
+        assert_eq!(auth.state().challenges.len(), 2);
+        let sweeper = auth.spawn_sweeper();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(auth.state().challenges.is_empty());
+
+        // The sweeper stops once the service is gone.
+        drop(auth);
+        tokio::time::timeout(Duration::from_secs(1), sweeper).await.unwrap().unwrap();
+    }
+
+    /// Registering a taken user name fails and leaves the original keys in place.
+    #[tokio::test]
+    async fn test_duplicate_registration() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let auth = AuthImpl::default();
+        let x = register(&auth, &zkp, "alice").await;
+
+        // A second registration of the same name is refused.
+        let (y1, y2) = zkp.compute_pair(&ZKP::generate_random_below(&zkp.q));
+        let status = auth
+            .register(Request::new(RegisterRequest { user: "alice".to_string(), y1: y1.to_bytes_be(), y2: y2.to_bytes_be(), salt: Vec::new() }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::AlreadyExists);
+
+        // The original secret still logs in.
+        let (k, response) = challenge(&auth, &zkp, "alice").await;
+        assert!(answer(&auth, &zkp, &x, &k, &response).await.is_ok());
+    }
+
+    /// Keys are rotated with a proof under the old key, bound to the new keys,
+    /// or with a live session, after which only the new secret logs in.
+    #[tokio::test]
+    async fn test_rotate_credential() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let auth = AuthImpl::default();
+        let old = register(&auth, &zkp, "alice").await;
+
+        let rotation = |x: &BigUint| {
+            let (y1, y2) = zkp.compute_pair(x);
+            RotateCredentialRequest { user: "alice".to_string(), y1: y1.to_bytes_be(), y2: y2.to_bytes_be(), ..Default::default() }
+        };
+
+        // Without a proof or a session nothing changes.
+        let new = ZKP::generate_random_below(&zkp.q);
+        let status = auth.rotate_credential(Request::new(rotation(&new))).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        // A wrong answer under the old key is refused.
+        let (k, response) = rotation_challenge(&auth, &zkp, "alice").await;
+        let request = rotation_answer(&zkp, &new, &k, &response, rotation(&new));
+        let status = auth.rotate_credential(Request::new(request)).await.unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+
+        // A login answer captured on the wire cannot rotate the keys, and a
+        // rotation challenge cannot be used to log in.
+        let (k, response) = challenge(&auth, &zkp, "alice").await;
+        let s = zkp.response(&k, &BigUint::from_bytes_be(&response.c), &old);
+        let request = RotateCredentialRequest { auth_id: response.auth_id.clone(), s: s.to_bytes_be(), ..rotation(&new) };
+        let status = auth.rotate_credential(Request::new(request)).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+        assert!(answer(&auth, &zkp, &old, &k, &response).await.is_ok());
+        let (k, response) = rotation_challenge(&auth, &zkp, "alice").await;
+        assert_eq!(answer(&auth, &zkp, &old, &k, &response).await.unwrap_err().code(), Code::NotFound);
+
+        // An answer bound to other keys does not install these.
+        let attacker = ZKP::generate_random_below(&zkp.q);
+        let request = rotation_answer(&zkp, &old, &k, &response, rotation(&new));
+        let request = RotateCredentialRequest { y1: rotation(&attacker).y1, y2: rotation(&attacker).y2, ..request };
+        let status = auth.rotate_credential(Request::new(request)).await.unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+
+        // New keys outside the group are refused.
+        let (k, response) = rotation_challenge(&auth, &zkp, "alice").await;
+        let request = RotateCredentialRequest { y1: BigUint::from(1u32).to_bytes_be(), ..rotation(&new) };
+        let request = rotation_answer(&zkp, &old, &k, &response, request);
+        let status = auth.rotate_credential(Request::new(request)).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        // A rotation challenge answered under the old key rotates to the new key.
+        let (k, response) = rotation_challenge(&auth, &zkp, "alice").await;
+        let request = rotation_answer(&zkp, &old, &k, &response, rotation(&new));
+        auth.rotate_credential(Request::new(request)).await.unwrap();
+
+        let (k, response) = challenge(&auth, &zkp, "alice").await;
+        assert!(answer(&auth, &zkp, &old, &k, &response).await.is_err());
+        let (k, response) = challenge(&auth, &zkp, "alice").await;
+        let session_id = answer(&auth, &zkp, &new, &k, &response).await.unwrap().into_inner().session_id;
+
+        // A session of alice rotates her key, while a session of bob does not.
+        let newer = ZKP::generate_random_below(&zkp.q);
+        let bob = register(&auth, &zkp, "bob").await;
+        let (k, response) = challenge(&auth, &zkp, "bob").await;
+        let bob_session = answer(&auth, &zkp, &bob, &k, &response).await.unwrap().into_inner().session_id;
+        let request = RotateCredentialRequest { session_id: bob_session.clone(), ..rotation(&newer) };
+        let status = auth.rotate_credential(signed(&auth, "RotateCredential", &bob_session, request)).await.unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+
+        // A request without a valid MAC under the session key is refused.
+        let request = RotateCredentialRequest { session_id: session_id.clone(), ..rotation(&newer) };
+        let status = auth.rotate_credential(Request::new(request.clone())).await.unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+        let status = auth.rotate_credential(signed(&auth, "Logout", &session_id, request.clone())).await.unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+
+        auth.rotate_credential(signed(&auth, "RotateCredential", &session_id, request)).await.unwrap();
+        let (k, response) = challenge(&auth, &zkp, "alice").await;
+        assert!(answer(&auth, &zkp, &newer, &k, &response).await.is_ok());
+
+        // The rotation ended alice's old sessions, but not bob's.
+        assert!(auth.sessions.validate(&session_id).is_none());
+        assert!(auth.sessions.validate(&bob_session).is_some());
+    }
+
+    /// A session issued at login validates, refreshes, and stops working after logout.
+    #[tokio::test]
+    async fn test_session_rpcs() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let auth = AuthImpl::default();
+        let x = register(&auth, &zkp, "alice").await;
+        let (k, response) = challenge(&auth, &zkp, "alice").await;
+        let login = answer(&auth, &zkp, &x, &k, &response).await.unwrap().into_inner();
+
+        // The session belongs to alice and expires after the session TTL.
+        let session = auth
+            .validate_session(Request::new(ValidateSessionRequest { session_id: login.session_id.clone() }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(session.user, "alice");
+        assert_eq!(session.expires_at, login.expires_at);
+        assert_eq!(session.expires_at - session.created_at, ServerConfig::default().session_ttl.as_secs());
+
+        // Refreshing keeps the session alive.
+        let refreshed = auth
+            .refresh_session(signed(&auth, "RefreshSession", &login.session_id, RefreshSessionRequest { session_id: login.session_id.clone() }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(refreshed.expires_at >= login.expires_at);
+
+        // After logout the session is rejected everywhere.
+        auth.logout(signed(&auth, "Logout", &login.session_id, LogoutRequest { session_id: login.session_id.clone() })).await.unwrap();
+        let status = auth
+            .validate_session(Request::new(ValidateSessionRequest { session_id: login.session_id.clone() }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+        let status = auth
+            .logout(signed(&auth, "Logout", &login.session_id, LogoutRequest { session_id: login.session_id.clone() }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+
+    /// With tokens enabled, a login returns a token that verifies offline
+    /// against the published keys, also after the signing key is rotated.
+    #[tokio::test]
+    async fn test_signed_login_token() {
+        use rust_zero_knowledge::token::TokenVerifier;
+
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let config = ServerConfig {
+            token_ttl: Some(Duration::from_secs(300)),
+            token_key_lifetime: Duration::ZERO,
+            ..Default::default()
+        };
+        let auth = AuthImpl::new(Box::<MemoryStore>::default(), &config).unwrap();
+        let x = register(&auth, &zkp, "alice").await;
+
+        let published = |response: GetTokenKeysResponse| {
+            TokenVerifier::new(response.keys.into_iter().map(|key| rust_zero_knowledge::token::TokenKey {
+                key_id: key.key_id,
+                public_key: BigUint::from_bytes_be(&key.public_key),
+            }))
+        };
+
+        // The login token carries the user's claims.
+        let (k, response) = challenge(&auth, &zkp, "alice").await;
+        let token = answer(&auth, &zkp, &x, &k, &response).await.unwrap().into_inner().token;
+        let keys = auth.get_token_keys(Request::new(GetTokenKeysRequest {})).await.unwrap().into_inner();
+        assert_eq!(keys.keys.len(), 1);
+        let claims = published(keys).verify(&zkp, &token, SystemTime::now()).unwrap();
+        assert_eq!(claims.user, "alice");
+        assert_eq!(claims.group_id, DEFAULT_GROUP_ID);
+        assert_eq!(claims.expires_at - claims.issued_at, 300);
+
+        // After a rotation both keys are published, so the old token still verifies.
+        auth.rotate_token_key();
+        let keys = auth.get_token_keys(Request::new(GetTokenKeysRequest {})).await.unwrap().into_inner();
+        assert_eq!(keys.keys.len(), 2);
+        assert!(published(keys).verify(&zkp, &token, SystemTime::now()).is_some());
+
+        // The old key outlives the token by the verifiers' leeway.
+        let expired = SystemTime::UNIX_EPOCH + Duration::from_secs(claims.expires_at) + DEFAULT_LEEWAY / 2;
+        let mut issuer = auth.state().token_issuer.clone().unwrap();
+        issuer.prune(expired);
+        let verifier = TokenVerifier::new(issuer.public_keys());
+        assert!(verifier.verify(&zkp, &token, expired).is_some());
+
+        // Without the option no token is issued.
+        let auth = AuthImpl::default();
+        let x = register(&auth, &zkp, "alice").await;
+        let (k, response) = challenge(&auth, &zkp, "alice").await;
+        assert!(answer(&auth, &zkp, &x, &k, &response).await.unwrap().into_inner().token.is_empty());
+    }
+
+    /// The interceptor for other services accepts the login tokens this service
+    /// issues, but not a bare session id, which only the Auth service accepts
+    /// together with a MAC under the session key.
+    #[tokio::test]
+    async fn test_interceptor_accepts_tokens() {
+        use rust_zero_knowledge::interceptor::{authenticated_user, SessionInterceptor, SESSION_TOKEN_METADATA_KEY};
+        use rust_zero_knowledge::token::TokenVerifier;
+        use std::sync::RwLock;
+        use tonic::service::Interceptor;
+
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let config = ServerConfig { token_ttl: Some(Duration::from_secs(300)), ..Default::default() };
+        let auth = AuthImpl::new(Box::<MemoryStore>::default(), &config).unwrap();
+        let keys = auth.get_token_keys(Request::new(GetTokenKeysRequest {})).await.unwrap().into_inner();
+        let verifier = TokenVerifier::new(keys.keys.into_iter().map(|key| rust_zero_knowledge::token::TokenKey {
+            key_id: key.key_id,
+            public_key: BigUint::from_bytes_be(&key.public_key),
+        }));
+        let mut interceptor = SessionInterceptor::new().with_tokens(Arc::new(RwLock::new(verifier)));
+
+        // Log in and present the token to the interceptor.
+        let x = register(&auth, &zkp, "alice").await;
+        let (k, response) = challenge(&auth, &zkp, "alice").await;
+        let login = answer(&auth, &zkp, &x, &k, &response).await.unwrap().into_inner();
+        let mut request = Request::new(());
+        request.metadata_mut().insert_bin(SESSION_TOKEN_METADATA_KEY, MetadataValue::from_bytes(&login.token));
+        let request = interceptor.call(request).unwrap();
+        assert_eq!(authenticated_user(&request).unwrap().user_name, "alice");
+
+        // The session id alone is refused.
+        let mut request = Request::new(());
+        request.metadata_mut().insert("x-session-id", login.session_id.parse().unwrap());
+        assert_eq!(interceptor.call(request).unwrap_err().code(), Code::Unauthenticated);
+    }
+
+    /// Repeated wrong answers make the user back off, and a peer hitting its
+    /// limit is refused, both with a retry hint.
+    #[tokio::test]
+    async fn test_rate_limits() {
+        use tonic::transport::server::TcpConnectInfo;
+
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let limits = LimitConfig { peer_requests: 2, ..Default::default() };
+        let auth = AuthImpl::new(Box::<MemoryStore>::default(), &ServerConfig { limits, ..Default::default() }).unwrap();
+        register(&auth, &zkp, "alice").await;
+
+        // The first wrong answers are free; after them the user has to wait.
+        let wrong = ZKP::generate_random_below(&zkp.q);
+        for _ in 0..3 {
+            let (k, response) = challenge(&auth, &zkp, "alice").await;
+            assert!(answer(&auth, &zkp, &wrong, &k, &response).await.is_err());
+        }
+        let status = auth
+            .create_authentication_challenge(Request::new(AuthenticationChallengeRequest { user: "alice".to_string(), ..Default::default() }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert_eq!(status.metadata().get("retry-after").unwrap(), "1");
+
+        // A peer address gets two challenges per window, whatever the user.
+        let from_peer = |user: &str| {
+            let mut request = Request::new(AuthenticationChallengeRequest { user: user.to_string(), ..Default::default() });
+            request.extensions_mut().insert(TcpConnectInfo {
+                local_addr: None,
+                remote_addr: Some("10.0.0.1:4000".parse().unwrap()),
+            });
+            request
+        };
+        register(&auth, &zkp, "bob").await;
+        register(&auth, &zkp, "carol").await;
+        assert!(auth.create_authentication_challenge(from_peer("bob")).await.is_ok());
+        assert!(auth.create_authentication_challenge(from_peer("carol")).await.is_ok());
+        let status = auth.create_authentication_challenge(from_peer("dave")).await.unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+    }
+
+    /// Challenges fetched before a lockout cannot be answered during it, neither
+    /// to log in nor to rotate the credential.
+    #[tokio::test]
+    async fn test_lockout_applies_to_answers() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let limits = LimitConfig { free_failures: 3, lockout_threshold: 3, ..Default::default() };
+        let auth = AuthImpl::new(Box::<MemoryStore>::default(), &ServerConfig { limits, ..Default::default() }).unwrap();
+        let x = register(&auth, &zkp, "alice").await;
+
+        // Fetch the challenges up front, then lock the user out with wrong answers.
+        let mut challenges = Vec::new();
+        for _ in 0..5 {
+            challenges.push(challenge(&auth, &zkp, "alice").await);
+        }
+        let (k, response) = rotation_challenge(&auth, &zkp, "alice").await;
+        let wrong = ZKP::generate_random_below(&zkp.q);
+        for (k, response) in challenges.drain(..3) {
+            assert_eq!(answer(&auth, &zkp, &wrong, &k, &response).await.unwrap_err().code(), Code::PermissionDenied);
+        }
+
+        // Even the right answer to a remaining challenge is refused.
+        let (login_k, login_response) = &challenges[0];
+        let status = answer(&auth, &zkp, &x, login_k, login_response).await.unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert!(status.metadata().get("retry-after").is_some());
+
+        let (y1, y2) = zkp.compute_pair(&ZKP::generate_random_below(&zkp.q));
+        let request = RotateCredentialRequest {
+            user: "alice".to_string(),
+            y1: y1.to_bytes_be(),
+            y2: y2.to_bytes_be(),
+            ..Default::default()
+        };
+        let request = rotation_answer(&zkp, &x, &k, &response, request);
+        let status = auth.rotate_credential(Request::new(request)).await.unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+    }
+
+    /// Asking for a challenge does not reveal whether a user exists: unknown
+    /// users get a stable salt of the same shape, and their logins fail like
+    /// a wrong answer does.
+    #[tokio::test]
+    async fn test_unknown_user_indistinguishable() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let config = ServerConfig { server_secret: Some("secret".to_string()), ..Default::default() };
+        let auth = AuthImpl::new(Box::<MemoryStore>::default(), &config).unwrap();
+        let x = register(&auth, &zkp, "alice").await;
+
+        // The unknown user gets a challenge with a salt like a client's.
+        let (k, unknown) = challenge(&auth, &zkp, "mallory").await;
+        assert_eq!(unknown.salt.len(), 16);
+        assert!(unknown.salt.iter().all(u8::is_ascii_alphanumeric));
+
+        // The salt stays the same across requests and restarts with the same secret.
+        let (_, again) = challenge(&auth, &zkp, "mallory").await;
+        assert_eq!(again.salt, unknown.salt);
+        let restarted = AuthImpl::new(Box::<MemoryStore>::default(), &config).unwrap();
+        assert_eq!(challenge(&restarted, &zkp, "mallory").await.1.salt, unknown.salt);
+
+        // Answering fails exactly as a wrong answer for a known user does.
+        let status = answer(&auth, &zkp, &x, &k, &unknown).await.unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        let (k, known) = challenge(&auth, &zkp, "alice").await;
+        let wrong = ZKP::generate_random_below(&zkp.q);
+        assert_eq!(answer(&auth, &zkp, &wrong, &k, &known).await.unwrap_err().code(), Code::PermissionDenied);
+    }
+
+    /// A token issued under the published key redeems once; a second
+    /// redemption and a wrong output are refused. The key survives a restart
+    /// with the same secret.
+    #[tokio::test]
+    async fn test_access_token_redeem() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let config = ServerConfig { access_token_secret: Some("secret".to_string()), ..Default::default() };
+        let auth = AuthImpl::new(Box::<MemoryStore>::default(), &config).unwrap();
+        let x = register(&auth, &zkp, "alice").await;
+        let (k, response) = challenge(&auth, &zkp, "alice").await;
+        let login = answer(&auth, &zkp, &x, &k, &response).await.unwrap().into_inner();
+
+        let key = auth.get_access_token_key(Request::new(GetAccessTokenKeyRequest {})).await.unwrap().into_inner();
+        let token_key = BigUint::from_bytes_be(&key.public_key);
+        let restarted = AuthImpl::new(Box::<MemoryStore>::default(), &config).unwrap();
+        let again = restarted.get_access_token_key(Request::new(GetAccessTokenKeyRequest {})).await.unwrap().into_inner();
+        assert_eq!(again.public_key, key.public_key);
+
+        let token_input = b"token input".to_vec();
+        let (blind, blinded) = zkp.oprf_blind(&token_input);
+        let issued = auth
+            .issue_token(signed(&auth, "IssueToken", &login.session_id, IssueTokenRequest {
+                session_id: login.session_id.clone(),
+                blinded_element: blinded.to_bytes_be(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let proof = DleqProof {
+            r1: BigUint::from_bytes_be(&issued.proof_r1),
+            r2: BigUint::from_bytes_be(&issued.proof_r2),
+            s: BigUint::from_bytes_be(&issued.proof_s),
+        };
+        let evaluated = BigUint::from_bytes_be(&issued.evaluated_element);
+        let unblinded = zkp.oprf_unblind(&token_key, &blinded, &evaluated, &proof, &blind).unwrap();
+        let token_output = zkp.oprf_finalize(&token_input, &unblinded).to_vec();
+
+        // The evaluation does not check out under any other key.
+        let (other_key, _) = zkp.compute_pair(&ZKP::generate_random_below(&zkp.q));
+        assert!(zkp.oprf_unblind(&other_key, &blinded, &evaluated, &proof, &blind).is_none());
+
+        let mut wrong = token_output.clone();
+        wrong[0] ^= 1;
+        let status = auth
+            .redeem_token(Request::new(RedeemTokenRequest { token_input: token_input.clone(), token_output: wrong }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+
+        auth.redeem_token(Request::new(RedeemTokenRequest { token_input: token_input.clone(), token_output: token_output.clone() }))
+            .await
+            .unwrap();
+        let status = auth
+            .redeem_token(Request::new(RedeemTokenRequest { token_input, token_output }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::AlreadyExists);
+    }
+
+    /// Only a few blind credential issuances can be open at once, whichever
+    /// users opened them, and the nonces of unanswered ones expire and are swept.
+    #[tokio::test]
+    async fn test_open_credentials_capped() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let config = ServerConfig {
+            challenge_ttl: Duration::from_millis(200),
+            max_open_credentials: 2,
+            ..Default::default()
+        };
+        let auth = AuthImpl::new(Box::<MemoryStore>::default(), &config).unwrap();
+        let mut sessions = Vec::new();
+        for user in ["alice", "bob", "carol"] {
+            let x = register(&auth, &zkp, user).await;
+            let (k, response) = challenge(&auth, &zkp, user).await;
+            sessions.push(answer(&auth, &zkp, &x, &k, &response).await.unwrap().into_inner().session_id);
+        }
+        let open = |session_id: &String| {
+            let request = BlindCredentialCommitmentRequest { session_id: session_id.clone() };
+            auth.create_blind_credential_commitment(signed(&auth, "CreateBlindCredentialCommitment", session_id, request))
+        };
+
+        // Two users take the two slots; a third user is refused as well.
+        let first = open(&sessions[0]).await.unwrap().into_inner();
+        open(&sessions[1]).await.unwrap();
+        assert_eq!(open(&sessions[2]).await.unwrap_err().code(), Code::ResourceExhausted);
+        assert_eq!(open(&sessions[0]).await.unwrap_err().code(), Code::ResourceExhausted);
+
+        // Once the nonces expire they no longer count, cannot be answered and
+        // are swept.
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let session_id = &sessions[0];
+        open(&sessions[2]).await.unwrap();
+        let status = auth
+            .issue_blind_credential(signed(&auth, "IssueBlindCredential", session_id, IssueBlindCredentialRequest {
+                session_id: session_id.clone(),
+                credential_id: first.credential_id,
+                e: vec![1],
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::DeadlineExceeded);
+        assert_eq!(auth.sweep_challenges(), 1);
+        assert_eq!(auth.state().credential_nonces.len(), 1);
+    }
+
+    /// A real user and an unknown one get challenges of the same shape, the
+    /// unknown user's stand-in salt is the same on every call, and membership
+    /// paths are only given to the user's own session.
+    #[tokio::test]
+    async fn test_unknown_user_response_shape() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let config = ServerConfig { server_secret: Some("secret".to_string()), ..Default::default() };
+        let auth = AuthImpl::new(Box::<MemoryStore>::default(), &config).unwrap();
+
+        // Register alice with a salt like the client's.
+        let x = ZKP::generate_random_below(&zkp.q);
+        let (y1, y2) = zkp.compute_pair(&x);
+        let salt = ZKP::generate_random_string(16).into_bytes();
+        auth.register(Request::new(RegisterRequest { user: "alice".to_string(), y1: y1.to_bytes_be(), y2: y2.to_bytes_be(), salt: salt.clone() }))
+            .await
+            .unwrap();
+
+        let (k, real) = challenge(&auth, &zkp, "alice").await;
+        let (_, unknown) = challenge(&auth, &zkp, "mallory").await;
+        assert_eq!(real.salt, salt);
+        for response in [&real, &unknown] {
+            assert_eq!(response.auth_id.len(), real.auth_id.len());
+            assert_eq!(response.salt.len(), 16);
+            assert!(response.salt.iter().all(u8::is_ascii_alphanumeric));
+            assert!(BigUint::from_bytes_be(&response.c) < zkp.q);
+            let server_share = BigUint::from_bytes_be(&response.server_share);
+            assert_eq!(server_share.modpow(&zkp.q, &zkp.p), BigUint::from(1u32));
+        }
+        for _ in 0..3 {
+            let (_, again) = challenge(&auth, &zkp, "mallory").await;
+            assert_eq!(again.salt, unknown.salt);
+            assert_ne!(again.auth_id, unknown.auth_id);
+        }
+
+        // Without a session, asking for a path is refused alike for both.
+        for user in ["alice", "mallory"] {
+            let request = GetMembershipPathRequest { user: user.to_string(), session_id: String::new() };
+            let status = auth.get_membership_path(Request::new(request)).await.unwrap_err();
+            assert_eq!(status.code(), Code::Unauthenticated);
+        }
+
+        // Alice's session gets her own path, but not anyone else's.
+        let session_id = answer(&auth, &zkp, &x, &k, &real).await.unwrap().into_inner().session_id;
+        let request = GetMembershipPathRequest { user: "mallory".to_string(), session_id: session_id.clone() };
+        let status = auth.get_membership_path(signed(&auth, "GetMembershipPath", &session_id, request)).await.unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+        let request = GetMembershipPathRequest { user: "alice".to_string(), session_id: session_id.clone() };
+        let path = auth
+            .get_membership_path(signed(&auth, "GetMembershipPath", &session_id, request))
+            .await
+            .unwrap()
+            .into_inner();
+        let path_ok = MerkleTree::verify_path(
+            &Sha256Hasher,
+            &path.root,
+            &ZKP::registration_leaf(&y1, &y2),
+            &MerklePath { index: path.leaf_index as usize, leaf_count: path.leaf_count as usize, siblings: path.siblings },
+        );
+        assert!(path_ok);
+    }
+}
@@ -0,0 +1,175 @@
+//! Sessions issued after a successful login.
+
+use crate::ZKP;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// What the server knows about the client that opened a session.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientMetadata {
+    /// Address of the peer that logged in, if known.
+    pub peer: Option<String>,
+    /// The `user-agent` the client sent, if any.
+    pub user_agent: Option<String>,
+}
+
+/// A login session.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub id: String,
+    pub user_name: String,
+    /// Key derived from the login transcript, shared with the client.
+    pub session_key: [u8; 32],
+    pub created_at: SystemTime,
+    pub expires_at: SystemTime,
+    pub client: ClientMetadata,
+}
+
+impl Session {
+    /// Returns true once the session is past its expiry.
+    pub fn is_expired(&self) -> bool {
+        SystemTime::now() >= self.expires_at
+    }
+}
+
+/// Keeps the live sessions. Every session lives for `ttl` after it was
+/// created or last refreshed; expired sessions are never handed out and are
+/// dropped by `sweep`.
+#[derive(Debug)]
+pub struct SessionStore {
+    sessions: Mutex<HashMap<String, Session>>,
+    ttl: Duration,
+}
+
+impl SessionStore {
+    pub fn new(ttl: Duration) -> Self {
+        SessionStore {
+            sessions: Mutex::default(),
+            ttl,
+        }
+    }
+
+    /// How long a session lives without being refreshed.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Opens a session for `user_name` under a fresh random id.
+    pub fn create(&self, user_name: &str, session_key: [u8; 32], client: ClientMetadata) -> Session {
+        let created_at = SystemTime::now();
+        let session = Session {
+            id: ZKP::generate_random_string(32),
+            user_name: user_name.to_string(),
+            session_key,
+            created_at,
+            expires_at: created_at + self.ttl,
+            client,
+        };
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(session.id.clone(), session.clone());
+        session
+    }
+
+    /// Returns the session `id` if it exists and has not expired.
+    pub fn validate(&self, id: &str) -> Option<Session> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get(id)?;
+        if session.is_expired() {
+            sessions.remove(id);
+            return None;
+        }
+        Some(session.clone())
+    }
+
+    /// Extends a live session by the TTL, counted from now.
+    ///
+    /// # Returns
+    /// The refreshed session, or `None` if it does not exist or has expired.
+    pub fn refresh(&self, id: &str) -> Option<Session> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get_mut(id)?;
+        if session.is_expired() {
+            sessions.remove(id);
+            return None;
+        }
+        session.expires_at = SystemTime::now() + self.ttl;
+        Some(session.clone())
+    }
+
+    /// Ends a session, returning it if it was live.
+    pub fn remove(&self, id: &str) -> Option<Session> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .remove(id)
+            .filter(|session| !session.is_expired())
+    }
+
+    /// Ends every session of `user_name` and returns how many were removed.
+    pub fn remove_user(&self, user_name: &str) -> usize {
+        let mut sessions = self.sessions.lock().unwrap();
+        let before = sessions.len();
+        sessions.retain(|_, session| session.user_name != user_name);
+        before - sessions.len()
+    }
+
+    /// Drops all expired sessions and returns how many were removed.
+    pub fn sweep(&self) -> usize {
+        let mut sessions = self.sessions.lock().unwrap();
+        let before = sessions.len();
+        sessions.retain(|_, session| !session.is_expired());
+        before - sessions.len()
+    }
+
+    /// Number of stored sessions, including expired ones not yet swept.
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Sessions validate until they expire, refresh extends them and logout ends them.
+    #[test]
+    fn test_session_lifecycle() {
+        let store = SessionStore::new(Duration::from_millis(100));
+
+        // A fresh session validates and carries its user.
+        let session = store.create("alice", [7; 32], ClientMetadata::default());
+        assert_eq!(store.validate(&session.id).unwrap().user_name, "alice");
+        assert!(store.validate("unknown").is_none());
+
+        // Refreshing moves the expiry forward.
+        std::thread::sleep(Duration::from_millis(60));
+        let refreshed = store.refresh(&session.id).unwrap();
+        assert!(refreshed.expires_at > session.expires_at);
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(store.validate(&session.id).is_some());
+
+        // Once expired, the session is gone and cannot be refreshed.
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(store.validate(&session.id).is_none());
+        assert!(store.refresh(&session.id).is_none());
+
+        // Logout ends a live session; sweeping drops expired ones.
+        let other = store.create("bob", [8; 32], ClientMetadata::default());
+        assert!(store.remove(&other.id).is_some());
+        assert!(store.validate(&other.id).is_none());
+        store.create("carol", [9; 32], ClientMetadata::default());
+        store.create("dave", [10; 32], ClientMetadata::default());
+        store.create("dave", [11; 32], ClientMetadata::default());
+        assert_eq!(store.remove_user("dave"), 2);
+        std::thread::sleep(Duration::from_millis(120));
+        assert_eq!(store.sweep(), 1);
+        assert!(store.is_empty());
+    }
+}
@@ -0,0 +1,298 @@
+// Storage for registered users.
+//
+// The server talks to a `UserStore` so registrations can live in memory (the
+// default, wiped on restart) or in an embedded SQLite database on disk.
+use num_bigint::BigUint;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use std::sync::Mutex;
+
+// Identifier of the group returned by `ZKP::get_constants` (RFC 5114, 1024-bit
+// MODP group with a 160-bit prime order subgroup). Migration 2 writes this id
+// as a literal for existing users, so it must not change.
+pub const DEFAULT_GROUP_ID: &str = "rfc5114-1024-160";
+
+// Registration data of a single user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserRecord {
+    pub user_name: String,
+    pub y1: BigUint,
+    pub y2: BigUint,
+    //salt the client mixes into its password to derive the secret
+    pub salt: Vec<u8>,
+    //group the public values y1 and y2 live in
+    pub group_id: String,
+}
+
+#[derive(Debug)]
+pub struct StoreError(String);
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "user store error: {}", self.0)
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(error: rusqlite::Error) -> Self {
+        StoreError(error.to_string())
+    }
+}
+
+pub type StoreResult<T> = Result<T, StoreError>;
+
+// A place to keep registered users. Implementations synchronize internally, so
+// a store can be shared between request handlers.
+pub trait UserStore: fmt::Debug + Send + Sync {
+    // Returns the record of `user_name`, if registered.
+    fn get(&self, user_name: &str) -> StoreResult<Option<UserRecord>>;
+    // Inserts the record of a new user. Returns false, and leaves the store
+    // untouched, if the user is already registered.
+    fn insert(&self, record: &UserRecord) -> StoreResult<bool>;
+    // Replaces the record of a registered user. Returns false if the user is
+    // not registered.
+    fn update(&self, record: &UserRecord) -> StoreResult<bool>;
+    // Returns all records ordered by user name.
+    fn all(&self) -> StoreResult<Vec<UserRecord>>;
+    // Marks the access token input `input` as redeemed. Returns false if it was
+    // redeemed before. Kept next to the users so that a persistent store also
+    // remembers spent tokens across restarts.
+    fn spend_token(&self, input: &[u8]) -> StoreResult<bool>;
+}
+
+// Keeps the users in a map; everything is lost when the server stops.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    users: Mutex<HashMap<String, UserRecord>>,
+    spent_tokens: Mutex<HashSet<Vec<u8>>>,
+}
+
+impl UserStore for MemoryStore {
+    fn get(&self, user_name: &str) -> StoreResult<Option<UserRecord>> {
+        Ok(self.users.lock().unwrap().get(user_name).cloned())
+    }
+
+    fn insert(&self, record: &UserRecord) -> StoreResult<bool> {
+        let mut users = self.users.lock().unwrap();
+        if users.contains_key(&record.user_name) {
+            return Ok(false);
+        }
+        users.insert(record.user_name.clone(), record.clone());
+        Ok(true)
+    }
+
+    fn update(&self, record: &UserRecord) -> StoreResult<bool> {
+        match self.users.lock().unwrap().get_mut(&record.user_name) {
+            Some(user) => {
+                *user = record.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn all(&self) -> StoreResult<Vec<UserRecord>> {
+        let mut users: Vec<UserRecord> = self.users.lock().unwrap().values().cloned().collect();
+        users.sort_by(|a, b| a.user_name.cmp(&b.user_name));
+        Ok(users)
+    }
+
+    fn spend_token(&self, input: &[u8]) -> StoreResult<bool> {
+        Ok(self.spent_tokens.lock().unwrap().insert(input.to_vec()))
+    }
+}
+
+// Schema migrations, applied in order. The number of applied migrations is kept
+// in SQLite's `user_version`, so only append to this list. Applied migrations
+// never change, which is why the group id default below is `DEFAULT_GROUP_ID`
+// spelled out rather than built from it; a test keeps the two in sync.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE users (
+        user_name TEXT PRIMARY KEY,
+        y1 BLOB NOT NULL,
+        y2 BLOB NOT NULL
+    );",
+    "ALTER TABLE users ADD COLUMN salt BLOB NOT NULL DEFAULT x'';
+     ALTER TABLE users ADD COLUMN group_id TEXT NOT NULL DEFAULT 'rfc5114-1024-160';",
+    "CREATE TABLE spent_tokens (
+        input BLOB PRIMARY KEY
+    );",
+];
+
+// Keeps the users in an SQLite database file.
+#[derive(Debug)]
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    // Opens (or creates) the database at `path` and brings its schema up to date.
+    pub fn open(path: impl AsRef<Path>) -> StoreResult<Self> {
+        let mut connection = Connection::open(path)?;
+        migrate(&mut connection)?;
+        Ok(SqliteStore { connection: Mutex::new(connection) })
+    }
+}
+
+// Applies the migrations that have not run yet, each in its own transaction.
+fn migrate(connection: &mut Connection) -> StoreResult<()> {
+    let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(StoreError(format!(
+            "database schema version {} is newer than this server ({})",
+            version,
+            MIGRATIONS.len()
+        )));
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", index + 1)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+fn read_record(row: &rusqlite::Row) -> rusqlite::Result<UserRecord> {
+    Ok(UserRecord {
+        user_name: row.get(0)?,
+        y1: BigUint::from_bytes_be(&row.get::<_, Vec<u8>>(1)?),
+        y2: BigUint::from_bytes_be(&row.get::<_, Vec<u8>>(2)?),
+        salt: row.get(3)?,
+        group_id: row.get(4)?,
+    })
+}
+
+impl UserStore for SqliteStore {
+    fn get(&self, user_name: &str) -> StoreResult<Option<UserRecord>> {
+        let connection = self.connection.lock().unwrap();
+        let record = connection
+            .query_row(
+                "SELECT user_name, y1, y2, salt, group_id FROM users WHERE user_name = ?1",
+                params![user_name],
+                read_record,
+            )
+            .optional()?;
+        Ok(record)
+    }
+
+    fn insert(&self, record: &UserRecord) -> StoreResult<bool> {
+        let connection = self.connection.lock().unwrap();
+        let inserted = connection.execute(
+            "INSERT OR IGNORE INTO users (user_name, y1, y2, salt, group_id) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                record.user_name,
+                record.y1.to_bytes_be(),
+                record.y2.to_bytes_be(),
+                record.salt,
+                record.group_id
+            ],
+        )?;
+        Ok(inserted == 1)
+    }
+
+    fn update(&self, record: &UserRecord) -> StoreResult<bool> {
+        let connection = self.connection.lock().unwrap();
+        let updated = connection.execute(
+            "UPDATE users SET y1 = ?2, y2 = ?3, salt = ?4, group_id = ?5 WHERE user_name = ?1",
+            params![
+                record.user_name,
+                record.y1.to_bytes_be(),
+                record.y2.to_bytes_be(),
+                record.salt,
+                record.group_id
+            ],
+        )?;
+        Ok(updated == 1)
+    }
+
+    fn all(&self) -> StoreResult<Vec<UserRecord>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement =
+            connection.prepare("SELECT user_name, y1, y2, salt, group_id FROM users ORDER BY user_name")?;
+        let users = statement.query_map([], read_record)?.collect::<rusqlite::Result<_>>()?;
+        Ok(users)
+    }
+
+    fn spend_token(&self, input: &[u8]) -> StoreResult<bool> {
+        let connection = self.connection.lock().unwrap();
+        let inserted = connection.execute("INSERT OR IGNORE INTO spent_tokens (input) VALUES (?1)", params![input])?;
+        Ok(inserted == 1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(user_name: &str, y: u32) -> UserRecord {
+        UserRecord {
+            user_name: user_name.to_string(),
+            y1: BigUint::from(y),
+            y2: BigUint::from(y + 1),
+            salt: vec![y as u8; 16],
+            group_id: DEFAULT_GROUP_ID.to_string(),
+        }
+    }
+
+    /// Registrations written to the SQLite store survive reopening the database.
+    #[test]
+    fn test_sqlite_store_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("users.db");
+
+        // Register two users and replace one of them; a second insert is refused.
+        let store = SqliteStore::open(&path).unwrap();
+        assert!(store.insert(&record("bob", 5)).unwrap());
+        assert!(store.insert(&record("alice", 3)).unwrap());
+        assert!(!store.insert(&record("bob", 9)).unwrap());
+        assert!(store.update(&record("bob", 7)).unwrap());
+        assert!(!store.update(&record("carol", 1)).unwrap());
+        drop(store);
+
+        // Reopen the file; the records come back ordered by name.
+        let store = SqliteStore::open(&path).unwrap();
+        assert_eq!(store.get("bob").unwrap(), Some(record("bob", 7)));
+        assert_eq!(store.get("carol").unwrap(), None);
+        assert_eq!(store.all().unwrap(), vec![record("alice", 3), record("bob", 7)]);
+
+        // Spent tokens are remembered as well.
+        assert!(store.spend_token(b"token").unwrap());
+        drop(store);
+        let store = SqliteStore::open(&path).unwrap();
+        assert!(!store.spend_token(b"token").unwrap());
+        assert!(store.spend_token(b"other").unwrap());
+    }
+
+    /// A database created by the first schema version is migrated in place.
+    #[test]
+    fn test_sqlite_store_migrates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("users.db");
+
+        // Create a version 1 database holding only y1 and y2.
+        let connection = Connection::open(&path).unwrap();
+        connection.execute_batch(MIGRATIONS[0]).unwrap();
+        connection.pragma_update(None, "user_version", 1).unwrap();
+        connection
+            .execute(
+                "INSERT INTO users (user_name, y1, y2) VALUES ('alice', ?1, ?2)",
+                params![vec![3u8], vec![4u8]],
+            )
+            .unwrap();
+        drop(connection);
+
+        // Opening it adds the salt and group id columns with their defaults.
+        let store = SqliteStore::open(&path).unwrap();
+        let alice = store.get("alice").unwrap().unwrap();
+        assert_eq!(alice.y1, BigUint::from(3u32));
+        assert!(alice.salt.is_empty());
+        assert_eq!(alice.group_id, DEFAULT_GROUP_ID);
+        assert!(MIGRATIONS[1].contains(&format!("DEFAULT '{}'", DEFAULT_GROUP_ID)));
+    }
+}
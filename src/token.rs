@@ -0,0 +1,323 @@
+//! Self-contained login tokens that services can verify without calling the
+//! Auth server.
+//!
+//! The server signs the claims of a login with a Schnorr key identified by a
+//! key id. Verifiers hold the published public keys by id. To rotate, the
+//! issuer starts signing with a fresh key but keeps publishing the old public
+//! key until every token it signed has expired, so verifiers that refresh
+//! their key set in the meantime never reject a valid token.
+
+use crate::{read_bytes, write_bytes, SchnorrSignature, ZKP};
+use num_bigint::BigUint;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const TOKEN_DOMAIN: &[u8] = b"zkp-auth/token";
+
+/// Clock skew a `TokenVerifier` tolerates between itself and the issuer by default.
+pub const DEFAULT_LEEWAY: Duration = Duration::from_secs(60);
+
+/// Seconds since the Unix epoch, as used in token claims.
+pub fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+/// What a token asserts about a login.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenClaims {
+    pub user: String,
+    /// Seconds since the Unix epoch.
+    pub issued_at: u64,
+    /// Seconds since the Unix epoch; the token is invalid from then on.
+    pub expires_at: u64,
+    /// How the user proved who they are.
+    pub auth_method: String,
+    /// Group the user's keys live in.
+    pub group_id: String,
+}
+
+impl TokenClaims {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_bytes(&mut out, self.user.as_bytes());
+        out.extend_from_slice(&self.issued_at.to_be_bytes());
+        out.extend_from_slice(&self.expires_at.to_be_bytes());
+        write_bytes(&mut out, self.auth_method.as_bytes());
+        write_bytes(&mut out, self.group_id.as_bytes());
+        out
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<TokenClaims> {
+        let mut input = bytes;
+        let user = read_string(&mut input)?;
+        let issued_at = read_u64(&mut input)?;
+        let expires_at = read_u64(&mut input)?;
+        let auth_method = read_string(&mut input)?;
+        let group_id = read_string(&mut input)?;
+        input.is_empty().then_some(TokenClaims {
+            user,
+            issued_at,
+            expires_at,
+            auth_method,
+            group_id,
+        })
+    }
+}
+
+/// Claims signed under the key `key_id`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedToken {
+    pub key_id: String,
+    pub claims: TokenClaims,
+    pub signature: SchnorrSignature,
+}
+
+impl SignedToken {
+    /// Serializes the token into its byte encoding.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_bytes(&mut out, self.key_id.as_bytes());
+        write_bytes(&mut out, &self.claims.to_bytes());
+        write_bytes(&mut out, &self.signature.to_bytes());
+        out
+    }
+
+    /// Parses a token from its byte encoding, returning `None` if it is malformed.
+    pub fn from_bytes(bytes: &[u8]) -> Option<SignedToken> {
+        let mut input = bytes;
+        let key_id = read_string(&mut input)?;
+        let claims = TokenClaims::from_bytes(read_bytes(&mut input)?)?;
+        let signature = SchnorrSignature::from_bytes(read_bytes(&mut input)?)?;
+        input.is_empty().then_some(SignedToken {
+            key_id,
+            claims,
+            signature,
+        })
+    }
+}
+
+/// The message a token signature covers: the key id and the claims.
+fn signed_message(key_id: &str, claims: &TokenClaims) -> Vec<u8> {
+    let mut message = TOKEN_DOMAIN.to_vec();
+    write_bytes(&mut message, key_id.as_bytes());
+    write_bytes(&mut message, &claims.to_bytes());
+    message
+}
+
+/// A published token verification key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenKey {
+    pub key_id: String,
+    /// `alpha^x mod p` for the signing secret `x`.
+    pub public_key: BigUint,
+}
+
+/// Signs tokens and keeps track of the keys verifiers still need.
+#[derive(Debug, Clone)]
+pub struct TokenIssuer {
+    current: TokenKey,
+    secret: BigUint,
+    created_at: SystemTime,
+    /// Earlier keys with the time until which they stay published.
+    retired: Vec<(TokenKey, SystemTime)>,
+}
+
+impl TokenIssuer {
+    /// Creates an issuer with a freshly generated signing key.
+    pub fn new(zkp: &ZKP) -> Self {
+        let secret = ZKP::generate_random_below(&zkp.q);
+        TokenIssuer {
+            current: TokenKey {
+                key_id: ZKP::generate_random_string(8),
+                public_key: zkp.alpha.modpow(&secret, &zkp.p),
+            },
+            secret,
+            created_at: SystemTime::now(),
+            retired: Vec::new(),
+        }
+    }
+
+    /// The key tokens are currently signed with.
+    pub fn current_key(&self) -> &TokenKey {
+        &self.current
+    }
+
+    /// When the current key was generated.
+    pub fn created_at(&self) -> SystemTime {
+        self.created_at
+    }
+
+    /// Signs `claims` with the current key.
+    pub fn issue(&self, zkp: &ZKP, claims: TokenClaims) -> SignedToken {
+        let key_id = self.current.key_id.clone();
+        let signature = zkp.sign(&signed_message(&key_id, &claims), &self.secret);
+        SignedToken {
+            key_id,
+            claims,
+            signature,
+        }
+    }
+
+    /// Switches to a freshly generated signing key. The old public key stays
+    /// published for `retain`, which must be at least the token lifetime plus
+    /// the leeway verifiers allow past expiry.
+    pub fn rotate(&mut self, zkp: &ZKP, retain: Duration) {
+        let old = std::mem::replace(self, TokenIssuer::new(zkp));
+        self.retired = old.retired;
+        self.retired.push((old.current, SystemTime::now() + retain));
+    }
+
+    /// Stops publishing retired keys whose retention has passed.
+    pub fn prune(&mut self, now: SystemTime) {
+        self.retired.retain(|(_, until)| now < *until);
+    }
+
+    /// All keys verifiers should accept: the current one first, then the
+    /// retired ones that are still published.
+    pub fn public_keys(&self) -> Vec<TokenKey> {
+        let now = SystemTime::now();
+        let retired = self
+            .retired
+            .iter()
+            .filter(|(_, until)| now < *until)
+            .map(|(key, _)| key.clone());
+        std::iter::once(self.current.clone()).chain(retired).collect()
+    }
+}
+
+/// Verifies tokens against a set of published keys.
+#[derive(Debug, Clone)]
+pub struct TokenVerifier {
+    keys: HashMap<String, BigUint>,
+    leeway: Duration,
+}
+
+impl Default for TokenVerifier {
+    fn default() -> Self {
+        TokenVerifier { keys: HashMap::new(), leeway: DEFAULT_LEEWAY }
+    }
+}
+
+impl TokenVerifier {
+    /// Creates a verifier accepting `keys`.
+    pub fn new(keys: impl IntoIterator<Item = TokenKey>) -> Self {
+        let mut verifier = TokenVerifier::default();
+        for key in keys {
+            verifier.insert(key);
+        }
+        verifier
+    }
+
+    /// Sets how far the verifier's clock may be off from the issuer's. Tokens are
+    /// accepted from `issued_at - leeway` until `expires_at + leeway`.
+    pub fn with_leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    /// Starts accepting tokens signed under `key`.
+    pub fn insert(&mut self, key: TokenKey) {
+        self.keys.insert(key.key_id, key.public_key);
+    }
+
+    /// Stops accepting tokens signed under `key_id`.
+    pub fn remove(&mut self, key_id: &str) {
+        self.keys.remove(key_id);
+    }
+
+    /// Checks a token's signature and lifetime at time `now`.
+    ///
+    /// # Returns
+    /// The claims, or `None` if the token is malformed, signed with an unknown
+    /// key, forged, not yet issued or expired, each time allowing for the leeway.
+    pub fn verify(&self, zkp: &ZKP, token: &[u8], now: SystemTime) -> Option<TokenClaims> {
+        let token = SignedToken::from_bytes(token)?;
+        let public_key = self.keys.get(&token.key_id)?;
+        let message = signed_message(&token.key_id, &token.claims);
+        if !zkp.verify_signature(&message, public_key, &token.signature) {
+            return None;
+        }
+
+        let now = unix_time(now);
+        let leeway = self.leeway.as_secs();
+        let issued = token.claims.issued_at <= now.saturating_add(leeway);
+        let live = now < token.claims.expires_at.saturating_add(leeway);
+        (issued && live).then_some(token.claims)
+    }
+}
+
+/// Reads a `write_bytes` string that must be valid UTF-8.
+fn read_string(input: &mut &[u8]) -> Option<String> {
+    String::from_utf8(read_bytes(input)?.to_vec()).ok()
+}
+
+/// Reads a big-endian `u64` from the front of `input`.
+fn read_u64(input: &mut &[u8]) -> Option<u64> {
+    if input.len() < 8 {
+        return None;
+    }
+    let (head, rest) = input.split_at(8);
+    *input = rest;
+    Some(u64::from_be_bytes(head.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn claims(now: SystemTime, lifetime: u64) -> TokenClaims {
+        TokenClaims {
+            user: "alice".to_string(),
+            issued_at: unix_time(now),
+            expires_at: unix_time(now) + lifetime,
+            auth_method: "chaum-pedersen".to_string(),
+            group_id: "rfc5114-1024-160".to_string(),
+        }
+    }
+
+    /// Tokens verify under the published key until they expire, and a rotated
+    /// key keeps verifying until it is pruned.
+    #[test]
+    fn test_signed_token() {
+        let (alpha, beta, p, q) = ZKP::get_constants();
+        let zkp = ZKP { alpha, beta, p, q };
+        let now = SystemTime::now();
+
+        // Issue a token valid for a minute and verify it with the published keys.
+        let mut issuer = TokenIssuer::new(&zkp);
+        let token = issuer.issue(&zkp, claims(now, 60)).to_bytes();
+        let verifier = TokenVerifier::new(issuer.public_keys()).with_leeway(Duration::ZERO);
+        assert_eq!(verifier.verify(&zkp, &token, now), Some(claims(now, 60)));
+
+        // It is rejected after expiry, under an unknown key, or with altered claims.
+        assert!(verifier.verify(&zkp, &token, now + Duration::from_secs(61)).is_none());
+        assert!(verifier.verify(&zkp, &token, now - Duration::from_secs(1)).is_none());
+        assert!(TokenVerifier::default().verify(&zkp, &token, now).is_none());
+        let mut forged = SignedToken::from_bytes(&token).unwrap();
+        forged.claims.user = "mallory".to_string();
+        assert!(verifier.verify(&zkp, &forged.to_bytes(), now).is_none());
+
+        // After rotation new tokens use a new key id, and both keys are published.
+        let old_key = issuer.current_key().clone();
+        issuer.rotate(&zkp, Duration::from_secs(60));
+        let rotated = issuer.issue(&zkp, claims(now, 60));
+        assert_ne!(rotated.key_id, old_key.key_id);
+        let verifier = TokenVerifier::new(issuer.public_keys());
+        assert!(verifier.verify(&zkp, &token, now).is_some());
+        assert!(verifier.verify(&zkp, &rotated.to_bytes(), now).is_some());
+
+        // The leeway covers a verifier clock that is ahead of or behind the issuer.
+        let lenient = TokenVerifier::new(issuer.public_keys()).with_leeway(Duration::from_secs(30));
+        assert!(lenient.verify(&zkp, &token, now + Duration::from_secs(89)).is_some());
+        assert!(lenient.verify(&zkp, &token, now + Duration::from_secs(91)).is_none());
+        assert!(lenient.verify(&zkp, &token, now - Duration::from_secs(29)).is_some());
+        assert!(lenient.verify(&zkp, &token, now - Duration::from_secs(31)).is_none());
+        assert!(TokenVerifier::new(issuer.public_keys()).verify(&zkp, &token, now + Duration::from_secs(61)).is_some());
+
+        // Once the retention has passed the old key is no longer published.
+        issuer.prune(now + Duration::from_secs(120));
+        assert_eq!(issuer.public_keys(), vec![issuer.current_key().clone()]);
+    }
+}